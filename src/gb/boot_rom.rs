@@ -0,0 +1,80 @@
+use std::fmt;
+
+const DMG_BOOT_ROM_SIZE: usize = 0x100;
+const CGB_BOOT_ROM_SIZE: usize = 0x900;
+
+// BootRomError describes why an image cannot be used as a boot ROM
+#[derive(Debug, PartialEq)]
+pub enum BootRomError {
+    InvalidSize(usize),
+}
+
+impl fmt::Display for BootRomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BootRomError::InvalidSize(len) => write!(
+                f,
+                "boot ROM must be {} bytes (DMG/MGB) or {} bytes (CGB), got {} bytes",
+                DMG_BOOT_ROM_SIZE, CGB_BOOT_ROM_SIZE, len
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BootRomError {}
+
+// BootRom is the program which runs before the game. It is laid over 0x0000...0x00FF, and over 0x0200...0x08FF
+// as well on CGB, leaving the header of the cartridge visible in between
+#[derive(Clone)]
+pub struct BootRom {
+    data: Vec<u8>,
+}
+
+impl BootRom {
+    pub fn new(data: Vec<u8>) -> Result<Self, BootRomError> {
+        match data.len() {
+            DMG_BOOT_ROM_SIZE | CGB_BOOT_ROM_SIZE => Ok(BootRom { data }),
+            len => Err(BootRomError::InvalidSize(len)),
+        }
+    }
+
+    // is_cgb returns true if the boot ROM is the one of CGB, which needs the CGB hardware to run
+    pub fn is_cgb(&self) -> bool {
+        self.data.len() == CGB_BOOT_ROM_SIZE
+    }
+
+    // read returns the byte at the address, or None if the boot ROM does not cover the address
+    pub fn read(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x0000...0x00FF | 0x0200...0x08FF => self.data.get(addr as usize).cloned(),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read() {
+        let boot_rom = BootRom::new(vec![0x42; DMG_BOOT_ROM_SIZE]).unwrap();
+        assert!(!boot_rom.is_cgb());
+        assert_eq!(Some(0x42), boot_rom.read(0x00FF));
+        assert_eq!(None, boot_rom.read(0x0100));
+        assert_eq!(None, boot_rom.read(0x0200));
+
+        let boot_rom = BootRom::new(vec![0x42; CGB_BOOT_ROM_SIZE]).unwrap();
+        assert!(boot_rom.is_cgb());
+        assert_eq!(None, boot_rom.read(0x0100));
+        assert_eq!(None, boot_rom.read(0x01FF));
+        assert_eq!(Some(0x42), boot_rom.read(0x0200));
+        assert_eq!(Some(0x42), boot_rom.read(0x08FF));
+        assert_eq!(None, boot_rom.read(0x0900));
+
+        assert_eq!(
+            Some(BootRomError::InvalidSize(0x200)),
+            BootRom::new(vec![0x00; 0x200]).err()
+        );
+    }
+}
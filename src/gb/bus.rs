@@ -3,4 +3,35 @@ pub trait Bus {
     fn read16(&self, addr: u16) -> u16;
     fn write8(&mut self, addr: u16, data: u8);
     fn write16(&mut self, addr: u16, data: u16);
+
+    // write8_direct writes a byte without the side effects which writes from the CPU have.
+    // Hardware components use it to update the registers they own.
+    fn write8_direct(&mut self, addr: u16, data: u8) {
+        self.write8(addr, data);
+    }
+
+    // cgb_mode returns true while the hardware runs in CGB mode
+    fn cgb_mode(&self) -> bool {
+        false
+    }
+
+    // dmg_compat returns true while CGB runs a DMG game, which is colored with the shades mapped through
+    // the color palettes
+    fn dmg_compat(&self) -> bool {
+        false
+    }
+
+    // read8_vram reads a byte from the given VRAM bank regardless of VBK, as the PPU does
+    fn read8_vram(&self, _bank: u8, addr: u16) -> u8 {
+        self.read8(addr)
+    }
+
+    // bg_color and obj_color return the 15-bit color stored in the color palette RAM of CGB
+    fn bg_color(&self, _palette: u8, _color_num: u8) -> u16 {
+        0x7FFF
+    }
+
+    fn obj_color(&self, _palette: u8, _color_num: u8) -> u16 {
+        0x7FFF
+    }
 }
@@ -0,0 +1,352 @@
+// The header occupies 0x0100...0x014F of the ROM
+pub const HEADER_END: usize = 0x0150;
+
+pub const LOGO_ADDR: usize = 0x0104;
+const TITLE_ADDR: usize = 0x0134;
+const MANUFACTURER_ADDR: usize = 0x013F;
+const CGB_FLAG_ADDR: usize = 0x0143;
+const NEW_LICENSEE_ADDR: usize = 0x0144;
+const SGB_FLAG_ADDR: usize = 0x0146;
+const CARTRIDGE_TYPE_ADDR: usize = 0x0147;
+const ROM_SIZE_ADDR: usize = 0x0148;
+const RAM_SIZE_ADDR: usize = 0x0149;
+const OLD_LICENSEE_ADDR: usize = 0x014B;
+const VERSION_ADDR: usize = 0x014C;
+const HEADER_CHECKSUM_ADDR: usize = 0x014D;
+const GLOBAL_CHECKSUM_ADDR: usize = 0x014E;
+
+// The boot ROM refuses to start unless the cartridge has this logo
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D, 0x00, 0x08, 0x11,
+    0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99, 0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E,
+    0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CgbSupport {
+    None,
+    Supported, // Works on both of DMG and CGB
+    Required,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CartridgeType {
+    RomOnly,
+    RomRam,
+    RomRamBattery,
+    Mbc1,
+    Mbc1Ram,
+    Mbc1RamBattery,
+    Mbc2,
+    Mbc2Battery,
+    Mmm01,
+    Mmm01Ram,
+    Mmm01RamBattery,
+    Mbc3TimerBattery,
+    Mbc3TimerRamBattery,
+    Mbc3,
+    Mbc3Ram,
+    Mbc3RamBattery,
+    Mbc5,
+    Mbc5Ram,
+    Mbc5RamBattery,
+    Mbc5Rumble,
+    Mbc5RumbleRam,
+    Mbc5RumbleRamBattery,
+    Mbc6,
+    Mbc7SensorRumbleRamBattery,
+    PocketCamera,
+    BandaiTama5,
+    HuC3,
+    HuC1RamBattery,
+    Unknown(u8),
+}
+
+impl CartridgeType {
+    pub fn new(v: u8) -> Self {
+        use self::CartridgeType::*;
+
+        match v {
+            0x00 => RomOnly,
+            0x01 => Mbc1,
+            0x02 => Mbc1Ram,
+            0x03 => Mbc1RamBattery,
+            0x05 => Mbc2,
+            0x06 => Mbc2Battery,
+            0x08 => RomRam,
+            0x09 => RomRamBattery,
+            0x0B => Mmm01,
+            0x0C => Mmm01Ram,
+            0x0D => Mmm01RamBattery,
+            0x0F => Mbc3TimerBattery,
+            0x10 => Mbc3TimerRamBattery,
+            0x11 => Mbc3,
+            0x12 => Mbc3Ram,
+            0x13 => Mbc3RamBattery,
+            0x19 => Mbc5,
+            0x1A => Mbc5Ram,
+            0x1B => Mbc5RamBattery,
+            0x1C => Mbc5Rumble,
+            0x1D => Mbc5RumbleRam,
+            0x1E => Mbc5RumbleRamBattery,
+            0x20 => Mbc6,
+            0x22 => Mbc7SensorRumbleRamBattery,
+            0xFC => PocketCamera,
+            0xFD => BandaiTama5,
+            0xFE => HuC3,
+            0xFF => HuC1RamBattery,
+            _ => Unknown(v),
+        }
+    }
+
+    // has_battery returns true if the cartridge keeps its RAM (and its clock) while the power is off
+    pub fn has_battery(&self) -> bool {
+        use self::CartridgeType::*;
+
+        matches!(
+            *self,
+            RomRamBattery
+                | Mbc1RamBattery
+                | Mbc2Battery
+                | Mmm01RamBattery
+                | Mbc3TimerBattery
+                | Mbc3TimerRamBattery
+                | Mbc3RamBattery
+                | Mbc5RamBattery
+                | Mbc5RumbleRamBattery
+                | Mbc7SensorRumbleRamBattery
+                | HuC3
+                | HuC1RamBattery
+        )
+    }
+
+    pub fn has_timer(&self) -> bool {
+        use self::CartridgeType::*;
+
+        matches!(*self, Mbc3TimerBattery | Mbc3TimerRamBattery | HuC3)
+    }
+
+    pub fn has_rumble(&self) -> bool {
+        use self::CartridgeType::*;
+
+        matches!(
+            *self,
+            Mbc5Rumble | Mbc5RumbleRam | Mbc5RumbleRamBattery | Mbc7SensorRumbleRamBattery
+        )
+    }
+}
+
+// CartridgeHeader holds the information about the game stored in the header of the ROM
+#[derive(Debug, Clone, PartialEq)]
+pub struct CartridgeHeader {
+    pub title: String,
+    pub manufacturer_code: Option<String>, // Only in newer cartridges
+    pub cgb_support: CgbSupport,
+    pub sgb_supported: bool,
+    pub old_licensee_code: u8,
+    pub new_licensee_code: Option<String>, // Only when the old licensee code is 0x33
+    pub cartridge_type: CartridgeType,
+    pub rom_size: Option<usize>, // In bytes, or None if the code is unknown
+    pub ram_size: Option<usize>, // In bytes, or None if the code is unknown
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+
+    pub header_checksum_valid: bool,
+    pub global_checksum_valid: bool,
+}
+
+impl CartridgeHeader {
+    // parse reads the header from the whole ROM, or returns None if the ROM is too short to have a header
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < HEADER_END {
+            return None;
+        }
+
+        let cgb_support = match data[CGB_FLAG_ADDR] {
+            0xC0 => CgbSupport::Required,
+            v if v & 0x80 != 0 => CgbSupport::Supported,
+            _ => CgbSupport::None,
+        };
+
+        // Newer cartridges have shortened the title for the manufacturer code and the CGB flag
+        let (title, manufacturer_code) = if cgb_support == CgbSupport::None {
+            (decode_ascii(&data[TITLE_ADDR..CGB_FLAG_ADDR + 1]), None)
+        } else {
+            let manufacturer = &data[MANUFACTURER_ADDR..CGB_FLAG_ADDR];
+            let manufacturer_code = if manufacturer.iter().all(|c| c.is_ascii_uppercase()) {
+                Some(decode_ascii(manufacturer))
+            } else {
+                None
+            };
+            (decode_ascii(&data[TITLE_ADDR..MANUFACTURER_ADDR]), manufacturer_code)
+        };
+
+        let old_licensee_code = data[OLD_LICENSEE_ADDR];
+        let new_licensee_code = if old_licensee_code == 0x33 {
+            Some(decode_ascii(&data[NEW_LICENSEE_ADDR..NEW_LICENSEE_ADDR + 2]))
+        } else {
+            None
+        };
+
+        let header_checksum = data[HEADER_CHECKSUM_ADDR];
+        let global_checksum = (data[GLOBAL_CHECKSUM_ADDR] as u16) << 8 | data[GLOBAL_CHECKSUM_ADDR + 1] as u16;
+
+        Some(CartridgeHeader {
+            title,
+            manufacturer_code,
+            cgb_support,
+            // The SGB functions are available only with the old licensee code 0x33
+            sgb_supported: data[SGB_FLAG_ADDR] == 0x03 && old_licensee_code == 0x33,
+            old_licensee_code,
+            new_licensee_code,
+            cartridge_type: CartridgeType::new(data[CARTRIDGE_TYPE_ADDR]),
+            rom_size: decode_rom_size(data[ROM_SIZE_ADDR]),
+            ram_size: decode_ram_size(data[RAM_SIZE_ADDR]),
+            version: data[VERSION_ADDR],
+            header_checksum,
+            global_checksum,
+
+            header_checksum_valid: header_checksum == compute_header_checksum(data),
+            global_checksum_valid: global_checksum == compute_global_checksum(data),
+        })
+    }
+}
+
+// compute_header_checksum returns the checksum of 0x0134...0x014C, which the boot ROM verifies
+pub fn compute_header_checksum(data: &[u8]) -> u8 {
+    data[TITLE_ADDR..HEADER_CHECKSUM_ADDR]
+        .iter()
+        .fold(0u8, |sum, &b| sum.wrapping_sub(b).wrapping_sub(1))
+}
+
+// compute_global_checksum returns the sum of all the bytes of the ROM except the global checksum itself,
+// which no hardware verifies
+pub fn compute_global_checksum(data: &[u8]) -> u16 {
+    data.iter()
+        .enumerate()
+        .filter(|&(i, _)| i != GLOBAL_CHECKSUM_ADDR && i != GLOBAL_CHECKSUM_ADDR + 1)
+        .fold(0u16, |sum, (_, &b)| sum.wrapping_add(b as u16))
+}
+
+fn decode_rom_size(v: u8) -> Option<usize> {
+    match v {
+        0x00...0x08 => Some(0x8000 << v),
+        _ => None,
+    }
+}
+
+fn decode_ram_size(v: u8) -> Option<usize> {
+    match v {
+        0x00 => Some(0),
+        0x01 => Some(0x800), // Unofficial, but seen in some homebrews
+        0x02 => Some(0x2000),
+        0x03 => Some(0x8000),
+        0x04 => Some(0x20000),
+        0x05 => Some(0x10000),
+        _ => None,
+    }
+}
+
+// decode_ascii reads a string padded with zeros, replacing characters which cannot be displayed
+fn decode_ascii(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .take_while(|&&c| c != 0x00)
+        .map(|&c| {
+            if c.is_ascii_graphic() || c == b' ' {
+                c as char
+            } else {
+                '?'
+            }
+        })
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom() -> Vec<u8> {
+        let mut data = vec![0x00; 0x8000];
+        data[TITLE_ADDR..TITLE_ADDR + 8].copy_from_slice(b"TESTGAME");
+        data[CARTRIDGE_TYPE_ADDR] = 0x13;
+        data[ROM_SIZE_ADDR] = 0x00;
+        data[RAM_SIZE_ADDR] = 0x03;
+        data[OLD_LICENSEE_ADDR] = 0x01;
+        data[VERSION_ADDR] = 0x02;
+        data
+    }
+
+    fn fix_checksums(data: &mut [u8]) {
+        data[HEADER_CHECKSUM_ADDR] = compute_header_checksum(data);
+        let global = compute_global_checksum(data);
+        data[GLOBAL_CHECKSUM_ADDR] = (global >> 8) as u8;
+        data[GLOBAL_CHECKSUM_ADDR + 1] = global as u8;
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut data = rom();
+        fix_checksums(&mut data);
+
+        let header = CartridgeHeader::parse(&data).unwrap();
+        assert_eq!("TESTGAME", header.title);
+        assert_eq!(None, header.manufacturer_code);
+        assert_eq!(CgbSupport::None, header.cgb_support);
+        assert!(!header.sgb_supported);
+        assert_eq!(0x01, header.old_licensee_code);
+        assert_eq!(None, header.new_licensee_code);
+        assert_eq!(CartridgeType::Mbc3RamBattery, header.cartridge_type);
+        assert!(header.cartridge_type.has_battery());
+        assert!(!header.cartridge_type.has_timer());
+        assert_eq!(Some(0x8000), header.rom_size);
+        assert_eq!(Some(0x8000), header.ram_size);
+        assert_eq!(0x02, header.version);
+        assert!(header.header_checksum_valid);
+        assert!(header.global_checksum_valid);
+    }
+
+    #[test]
+    fn test_parse_cgb() {
+        let mut data = rom();
+        data[MANUFACTURER_ADDR..CGB_FLAG_ADDR].copy_from_slice(b"ABCD");
+        data[CGB_FLAG_ADDR] = 0xC0;
+        data[NEW_LICENSEE_ADDR..NEW_LICENSEE_ADDR + 2].copy_from_slice(b"01");
+        data[SGB_FLAG_ADDR] = 0x03;
+        data[OLD_LICENSEE_ADDR] = 0x33;
+        data[ROM_SIZE_ADDR] = 0x06;
+        data[RAM_SIZE_ADDR] = 0x07;
+
+        let header = CartridgeHeader::parse(&data).unwrap();
+        assert_eq!("TESTGAME", header.title);
+        assert_eq!(Some("ABCD".to_string()), header.manufacturer_code);
+        assert_eq!(CgbSupport::Required, header.cgb_support);
+        assert!(header.sgb_supported);
+        assert_eq!(Some("01".to_string()), header.new_licensee_code);
+        assert_eq!(Some(0x200000), header.rom_size);
+        assert_eq!(None, header.ram_size);
+    }
+
+    #[test]
+    fn test_checksums() {
+        let mut data = rom();
+        fix_checksums(&mut data);
+
+        data[0x1000] = 0xFF;
+        let header = CartridgeHeader::parse(&data).unwrap();
+        assert!(header.header_checksum_valid);
+        assert!(!header.global_checksum_valid);
+
+        data[VERSION_ADDR] = 0x03;
+        let header = CartridgeHeader::parse(&data).unwrap();
+        assert!(!header.header_checksum_valid);
+    }
+
+    #[test]
+    fn test_too_short() {
+        assert_eq!(None, CartridgeHeader::parse(&[0x00; HEADER_END - 1]));
+    }
+}
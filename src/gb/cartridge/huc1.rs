@@ -0,0 +1,117 @@
+use super::{banked_addr, MemoryBankController, RAM_BANK_SIZE, ROM_BANK_SIZE};
+
+// HuC1 is the MBC of Hudson which has an infrared LED and sensor in place of the RAM enable
+pub struct HuC1 {
+    rom: Vec<u8>,
+    rom_bank: usize,
+    ram: Vec<u8>,
+    ram_bank: usize,
+
+    // 0xA000...0xBFFF accesses the infrared port instead of the RAM
+    ir_mode: bool,
+    ir_led: bool,
+}
+
+impl HuC1 {
+    pub fn new(data: Vec<u8>, ram_size: usize) -> Self {
+        HuC1 {
+            rom: data,
+            rom_bank: 1,
+            ram: vec![0x00; ram_size],
+            ram_bank: 0,
+
+            ir_mode: false,
+            ir_led: false,
+        }
+    }
+}
+
+impl MemoryBankController for HuC1 {
+    fn read(&self, addr: u16) -> u8 {
+        let addr = addr as usize;
+        match addr {
+            0x0000...0x3FFF => self.rom[addr],
+            0x4000...0x7FFF => self.rom[banked_addr(&self.rom, ROM_BANK_SIZE, self.rom_bank, addr)],
+            // The sensor never sees light from another device, which reads as 0 in bit 0
+            0xA000...0xBFFF if self.ir_mode => 0xC0,
+            0xA000...0xBFFF => self
+                .ram
+                .get(banked_addr(&self.ram, RAM_BANK_SIZE, self.ram_bank, addr))
+                .cloned()
+                .unwrap_or(0xFF),
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        let addr = addr as usize;
+        match addr {
+            0x0000...0x1FFF => self.ir_mode = data & 0x0F == 0x0E,
+            // Unlike MBC1, bank 0 can be mapped to 0x4000...0x7FFF as well
+            0x2000...0x3FFF => self.rom_bank = (data & 0x3F) as usize,
+            0x4000...0x5FFF => self.ram_bank = (data & 0x03) as usize,
+            0x6000...0x7FFF => (),
+            0xA000...0xBFFF if self.ir_mode => self.ir_led = data & 0x01 != 0,
+            0xA000...0xBFFF => {
+                let addr = banked_addr(&self.ram, RAM_BANK_SIZE, self.ram_bank, addr);
+                if let Some(byte) = self.ram.get_mut(addr) {
+                    *byte = data;
+                }
+            }
+            _ => (),
+        };
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_huc1() -> HuC1 {
+        // Every ROM bank is filled with its own number
+        let mut data = vec![0x00; 0x4000 * 64];
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = (i / 0x4000) as u8;
+        }
+        HuC1::new(data, 0x8000)
+    }
+
+    #[test]
+    fn test_rom_bank() {
+        let mut mbc = new_huc1();
+        assert_eq!(0x01, mbc.read(0x4000));
+
+        mbc.write(0x2000, 0x3F);
+        assert_eq!(0x3F, mbc.read(0x7FFF));
+        mbc.write(0x2000, 0x00);
+        assert_eq!(0x00, mbc.read(0x4000));
+    }
+
+    #[test]
+    fn test_ram_and_infrared() {
+        let mut mbc = new_huc1();
+        for bank in 0..4 {
+            mbc.write(0x4000, bank);
+            mbc.write(0xA000, bank + 0x10);
+        }
+        mbc.write(0x4000, 0x02);
+        assert_eq!(0x12, mbc.read(0xA000));
+
+        mbc.write(0x0000, 0x0E);
+        assert_eq!(0xC0, mbc.read(0xA000));
+        mbc.write(0xA000, 0x01);
+        assert!(mbc.ir_led);
+
+        // The RAM is left as it is while the infrared port is selected
+        mbc.write(0x0000, 0x00);
+        assert_eq!(0x12, mbc.read(0xA000));
+    }
+}
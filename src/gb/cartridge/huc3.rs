@@ -0,0 +1,397 @@
+use super::rtc::Clock;
+use super::save::{HuC3Footer, HUC3_FOOTER_SIZE, HUC3_MEMORY_SIZE};
+use super::{banked_addr, MemoryBankController, RAM_BANK_SIZE, ROM_BANK_SIZE};
+use std::rc::Rc;
+
+const MINUTES_PER_DAY: u64 = 1440;
+
+// Registers of the RTC chip, which are addressed a nibble at a time
+const MINUTES_INDEX: u8 = 0x00; // 3 nibbles
+const DAYS_INDEX: u8 = 0x03; // 4 nibbles
+const ALARM_MINUTES_INDEX: u8 = 0x10; // 3 nibbles
+const ALARM_DAYS_INDEX: u8 = 0x13; // 4 nibbles
+const ALARM_ENABLED_INDEX: u8 = 0x26;
+const TONE_INDEX: u8 = 0x27;
+
+// Commands written in the upper nibble to 0xA000...0xBFFF, with the argument in the lower nibble
+const COMMAND_READ: u8 = 0x1;
+const COMMAND_WRITE: u8 = 0x2;
+const COMMAND_WRITE_NEXT: u8 = 0x3;
+const COMMAND_INDEX_LOW: u8 = 0x4;
+const COMMAND_INDEX_HIGH: u8 = 0x5;
+const COMMAND_EXTENDED: u8 = 0x6;
+
+const EXTENDED_STATUS: u8 = 0x2;
+const EXTENDED_TONE: u8 = 0xE;
+
+// HuC3 is the MBC of Hudson with an RTC chip and a speaker, which the game talks to with commands
+// through 0xA000...0xBFFF
+pub struct HuC3 {
+    rom: Vec<u8>,
+    rom_bank: usize,
+    ram: Vec<u8>,
+    ram_bank: usize,
+
+    // Selects what 0xA000...0xBFFF accesses: the RAM (0x0 read only, 0xA), commands (0xB), responses (0xC),
+    // the ready flag (0xD) or the infrared port (0xE)
+    mode: u8,
+
+    clock: Rc<Clock>,
+    last_update: u64,
+    seconds: u64, // Seconds not counted into the minutes yet
+    minutes: u16, // Within a day, 12 bits
+    days: u16,
+
+    memory: [u8; HUC3_MEMORY_SIZE], // Nibbles of the chip other than the clock
+    index: u8,
+    response: u8,
+
+    tone: Option<u8>, // The tone requested to the speaker, which has not been taken by the frontend
+    ir_led: bool,
+}
+
+impl HuC3 {
+    pub fn new(data: Vec<u8>, ram_size: usize, clock: Rc<Clock>) -> Self {
+        let now = clock.now();
+
+        HuC3 {
+            rom: data,
+            rom_bank: 1,
+            ram: vec![0x00; ram_size],
+            ram_bank: 0,
+
+            mode: 0x00,
+
+            clock,
+            last_update: now,
+            seconds: 0,
+            minutes: 0,
+            days: 0,
+
+            memory: [0x00; HUC3_MEMORY_SIZE],
+            index: 0,
+            response: 0,
+
+            tone: None,
+            ir_led: false,
+        }
+    }
+
+    fn update(&mut self) {
+        let now = self.clock.now();
+        self.seconds += now.saturating_sub(self.last_update);
+        self.last_update = now;
+
+        let minutes = self.minutes as u64 + self.seconds / 60;
+        self.seconds %= 60;
+        self.minutes = (minutes % MINUTES_PER_DAY) as u16;
+        self.days = self.days.wrapping_add((minutes / MINUTES_PER_DAY) as u16);
+    }
+
+    fn read_nibble(&mut self, index: u8) -> u8 {
+        match index {
+            0x00...0x02 => {
+                self.update();
+                (self.minutes >> ((index - MINUTES_INDEX) * 4)) as u8 & 0x0F
+            }
+            0x03...0x06 => {
+                self.update();
+                (self.days >> ((index - DAYS_INDEX) * 4)) as u8 & 0x0F
+            }
+            _ => self.memory[index as usize],
+        }
+    }
+
+    fn write_nibble(&mut self, index: u8, data: u8) {
+        let data = (data & 0x0F) as u16;
+        match index {
+            0x00...0x02 => {
+                self.update();
+                let shift = (index - MINUTES_INDEX) * 4;
+                self.minutes = (self.minutes & !(0x0F << shift)) | data << shift;
+            }
+            0x03...0x06 => {
+                self.update();
+                let shift = (index - DAYS_INDEX) * 4;
+                self.days = (self.days & !(0x0F << shift)) | data << shift;
+            }
+            _ => self.memory[index as usize] = data as u8,
+        }
+    }
+
+    // nibbles returns the value stored in the nibbles of the memory from the index, the lowest nibble first
+    fn nibbles(&self, index: u8, len: u8) -> u16 {
+        (0..len).fold(0, |value, i| {
+            value | (self.memory[(index + i) as usize] as u16 & 0x0F) << (i * 4)
+        })
+    }
+
+    fn set_nibbles(&mut self, index: u8, len: u8, value: u16) {
+        for i in 0..len {
+            self.memory[(index + i) as usize] = (value >> (i * 4)) as u8 & 0x0F;
+        }
+    }
+
+    fn command(&mut self, data: u8) {
+        let (command, arg) = (data >> 4, data & 0x0F);
+        match command {
+            COMMAND_READ => {
+                self.response = self.read_nibble(self.index);
+                self.index = self.index.wrapping_add(1);
+            }
+            COMMAND_WRITE => self.write_nibble(self.index, arg),
+            COMMAND_WRITE_NEXT => {
+                self.write_nibble(self.index, arg);
+                self.index = self.index.wrapping_add(1);
+            }
+            COMMAND_INDEX_LOW => self.index = (self.index & 0xF0) | arg,
+            COMMAND_INDEX_HIGH => self.index = (self.index & 0x0F) | arg << 4,
+            COMMAND_EXTENDED => match arg {
+                EXTENDED_STATUS => self.response = 0x01,
+                EXTENDED_TONE => self.tone = Some(self.memory[TONE_INDEX as usize]),
+                _ => (),
+            },
+            _ => (),
+        }
+
+        // The upper nibble of the response mirrors the last command
+        self.response = command << 4 | (self.response & 0x0F);
+    }
+
+    fn ram_addr(&self, addr: usize) -> usize {
+        banked_addr(&self.ram, RAM_BANK_SIZE, self.ram_bank, addr)
+    }
+}
+
+impl MemoryBankController for HuC3 {
+    fn read(&self, addr: u16) -> u8 {
+        let addr = addr as usize;
+        match addr {
+            0x0000...0x3FFF => self.rom[addr],
+            0x4000...0x7FFF => self.rom[banked_addr(&self.rom, ROM_BANK_SIZE, self.rom_bank, addr)],
+            0xA000...0xBFFF => match self.mode {
+                0x00 | 0x0A => self.ram.get(self.ram_addr(addr)).cloned().unwrap_or(0xFF),
+                0x0C => self.response,
+                0x0D => 0x01, // Always ready to take the next command
+                0x0E => 0xC0, // No light from another device
+                _ => 0xFF,
+            },
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        let addr = addr as usize;
+        match addr {
+            0x0000...0x1FFF => self.mode = data & 0x0F,
+            0x2000...0x3FFF => self.rom_bank = (data & 0x7F) as usize,
+            0x4000...0x5FFF => self.ram_bank = (data & 0x0F) as usize,
+            0x6000...0x7FFF => (),
+            0xA000...0xBFFF => match self.mode {
+                0x0A => {
+                    let addr = self.ram_addr(addr);
+                    if let Some(byte) = self.ram.get_mut(addr) {
+                        *byte = data;
+                    }
+                }
+                0x0B => self.command(data),
+                0x0E => self.ir_led = data & 0x01 != 0,
+                _ => (),
+            },
+            _ => (),
+        };
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn footer(&mut self) -> Option<Vec<u8>> {
+        self.update();
+
+        // The seconds not counted into the minutes yet are kept by dating the save back
+        let footer = HuC3Footer {
+            timestamp: self.last_update.saturating_sub(self.seconds),
+            minutes: self.minutes,
+            days: self.days,
+            alarm_minutes: self.nibbles(ALARM_MINUTES_INDEX, 3),
+            alarm_days: self.nibbles(ALARM_DAYS_INDEX, 4),
+            alarm_enabled: self.memory[ALARM_ENABLED_INDEX as usize] & 0x01 != 0,
+        };
+
+        let mut data = footer.to_bytes();
+        data.extend_from_slice(&self.memory);
+        Some(data)
+    }
+
+    // load_footer takes the clock alone from the saves of other emulators, or with the memory from this one
+    fn load_footer(&mut self, data: &[u8]) -> bool {
+        let (clock, memory) = data.split_at(data.len().min(HUC3_FOOTER_SIZE));
+        let footer = match HuC3Footer::parse(clock) {
+            Some(footer) => footer,
+            None => return false,
+        };
+
+        match memory.len() {
+            0 => (),
+            HUC3_MEMORY_SIZE => self.memory.copy_from_slice(memory),
+            _ => return false,
+        }
+        self.set_nibbles(ALARM_MINUTES_INDEX, 3, footer.alarm_minutes);
+        self.set_nibbles(ALARM_DAYS_INDEX, 4, footer.alarm_days);
+        self.memory[ALARM_ENABLED_INDEX as usize] = footer.alarm_enabled as u8;
+
+        self.minutes = footer.minutes;
+        self.days = footer.days;
+        self.seconds = 0;
+        self.last_update = footer.timestamp;
+        self.update();
+        true
+    }
+
+    fn take_tone(&mut self) -> Option<u8> {
+        self.tone.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::rtc::tests::FakeClock;
+    use super::*;
+
+    fn new_huc3(clock: &FakeClock) -> HuC3 {
+        HuC3::new(vec![0x00; 0x4000 * 4], 0x8000, Rc::new(clock.clone()))
+    }
+
+    fn command(mbc: &mut HuC3, data: u8) -> u8 {
+        mbc.write(0x0000, 0x0B);
+        mbc.write(0xA000, data);
+        mbc.write(0x0000, 0x0C);
+        mbc.read(0xA000)
+    }
+
+    fn read_clock(mbc: &mut HuC3) -> (u16, u16) {
+        command(mbc, 0x40);
+        command(mbc, 0x50);
+        let nibbles: Vec<u16> = (0..7).map(|_| (command(mbc, 0x10) & 0x0F) as u16).collect();
+        let minutes = nibbles[0] | nibbles[1] << 4 | nibbles[2] << 8;
+        let days = nibbles[3] | nibbles[4] << 4 | nibbles[5] << 8 | nibbles[6] << 12;
+        (minutes, days)
+    }
+
+    #[test]
+    fn test_ram_modes() {
+        let mut mbc = new_huc3(&FakeClock::new());
+
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0x4000, 0x03);
+        mbc.write(0xA000, 0x42);
+        assert_eq!(0x42, mbc.read(0xA000));
+
+        // Mode 0 can read the RAM but not write it
+        mbc.write(0x0000, 0x00);
+        mbc.write(0xA000, 0x24);
+        assert_eq!(0x42, mbc.read(0xA000));
+
+        mbc.write(0x0000, 0x0D);
+        assert_eq!(0x01, mbc.read(0xA000));
+    }
+
+    #[test]
+    fn test_clock() {
+        let clock = FakeClock::new();
+        let mut mbc = new_huc3(&clock);
+
+        clock.forward(3 * 86400 + 61 * 60 + 59);
+        assert_eq!((61, 3), read_clock(&mut mbc));
+
+        // Set the clock to 23:59 of day 0x1234
+        command(&mut mbc, 0x40);
+        command(&mut mbc, 0x50);
+        for &nibble in &[0xF, 0x9, 0x5, 0x4, 0x3, 0x2, 0x1] {
+            command(&mut mbc, 0x30 | nibble);
+        }
+        assert_eq!((1439, 0x1234), read_clock(&mut mbc));
+
+        // The second left over from before carries into the next day
+        clock.forward(1);
+        assert_eq!((0, 0x1235), read_clock(&mut mbc));
+    }
+
+    #[test]
+    fn test_command_response() {
+        let mut mbc = new_huc3(&FakeClock::new());
+
+        // Write 0xA to 0x10 and read it back
+        command(&mut mbc, 0x40);
+        command(&mut mbc, 0x51);
+        command(&mut mbc, 0x2A);
+        assert_eq!(0x10, mbc.index);
+        assert_eq!(0x1A, command(&mut mbc, 0x10));
+        assert_eq!(0x11, mbc.index);
+
+        assert_eq!(0x61, command(&mut mbc, 0x62));
+    }
+
+    #[test]
+    fn test_tone() {
+        let mut mbc = new_huc3(&FakeClock::new());
+        assert_eq!(None, mbc.take_tone());
+
+        command(&mut mbc, 0x47);
+        command(&mut mbc, 0x52);
+        command(&mut mbc, 0x23);
+        command(&mut mbc, 0x6E);
+        assert_eq!(Some(0x03), mbc.take_tone());
+        assert_eq!(None, mbc.take_tone());
+    }
+
+    #[test]
+    fn test_footer() {
+        let clock = FakeClock::new();
+        let mut mbc = new_huc3(&clock);
+        clock.forward(2 * 86400 + 90 * 60 + 30);
+
+        // Set the alarm to 00:45 of day 3 and turn it on
+        command(&mut mbc, 0x40);
+        command(&mut mbc, 0x51);
+        for &nibble in &[0xD, 0x2, 0x0, 0x3, 0x0, 0x0, 0x0] {
+            command(&mut mbc, 0x30 | nibble);
+        }
+        command(&mut mbc, 0x46);
+        command(&mut mbc, 0x52);
+        command(&mut mbc, 0x21);
+
+        let footer = mbc.footer().unwrap();
+        assert_eq!(HUC3_FOOTER_SIZE + HUC3_MEMORY_SIZE, footer.len());
+        let saved = HuC3Footer::parse(&footer[..HUC3_FOOTER_SIZE]).unwrap();
+        assert_eq!((90, 2), (saved.minutes, saved.days));
+        assert_eq!(
+            (45, 3, true),
+            (saved.alarm_minutes, saved.alarm_days, saved.alarm_enabled)
+        );
+
+        // The clock catches up with the time passed since the save, the leftover 30 seconds included
+        clock.forward(86400 + 30);
+        let mut mbc = new_huc3(&clock);
+        assert!(mbc.load_footer(&footer));
+        assert_eq!((91, 3), read_clock(&mut mbc));
+        command(&mut mbc, 0x40);
+        command(&mut mbc, 0x51);
+        assert_eq!(0x1D, command(&mut mbc, 0x10));
+
+        // Saves of other emulators have the clock and the alarm alone
+        let mut mbc = new_huc3(&clock);
+        assert!(mbc.load_footer(&footer[..HUC3_FOOTER_SIZE]));
+        assert_eq!((91, 3), read_clock(&mut mbc));
+        command(&mut mbc, 0x46);
+        command(&mut mbc, 0x52);
+        assert_eq!(0x11, command(&mut mbc, 0x10));
+
+        assert!(!mbc.load_footer(&footer[..48]));
+    }
+}
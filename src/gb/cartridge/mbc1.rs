@@ -1,4 +1,9 @@
-use super::MemoryBankController;
+use super::header::{LOGO_ADDR, NINTENDO_LOGO};
+use super::{banked_addr, MemoryBankController, RAM_BANK_SIZE, ROM_BANK_SIZE};
+
+// Multicarts hold a game every 256KB, each of which has its own header
+const MULTICART_GAME_SIZE: usize = 0x40000;
+const MULTICART_ROM_SIZE: usize = 0x100000;
 
 enum MemoryModel {
     Model0,
@@ -7,42 +12,85 @@ enum MemoryModel {
 
 pub struct Mbc1 {
     rom: Vec<u8>,
-    rom_bank: usize,
     ram: Vec<u8>,
-    ram_bank: usize,
+
+    // The lower 5 bits of the ROM bank, and the 2 bits which either extend the ROM bank or select the RAM bank
+    bank1: usize,
+    bank2: usize,
 
     memory_model: MemoryModel,
     ram_enabled: bool,
+
+    // MBC1M connects only 4 bits of the lower bank to the ROM, which makes the upper bits select a game
+    multicart: bool,
 }
 
 impl Mbc1 {
-    pub fn new(data: Vec<u8>) -> Self {
+    pub fn new(data: Vec<u8>, ram_size: usize) -> Self {
+        let multicart = is_multicart(&data);
+
         Mbc1 {
             rom: data,
-            rom_bank: 1,
-            ram: vec![0x00; 0x8000],
-            ram_bank: 0,
+            ram: vec![0x00; ram_size],
+
+            bank1: 1,
+            bank2: 0,
 
             memory_model: MemoryModel::Model0,
             ram_enabled: false,
+
+            multicart,
+        }
+    }
+
+    fn bank1_bits(&self) -> usize {
+        if self.multicart {
+            4
+        } else {
+            5
+        }
+    }
+
+    // In model 1, the upper bits also switch 0x0000...0x3FFF and the RAM bank, which lets large ROMs
+    // map banks 0x20, 0x40 and 0x60 there
+    fn rom_bank0(&self) -> usize {
+        match self.memory_model {
+            MemoryModel::Model0 => 0,
+            MemoryModel::Model1 => self.bank2 << self.bank1_bits(),
+        }
+    }
+
+    fn rom_bank(&self) -> usize {
+        let bits = self.bank1_bits();
+        self.bank2 << bits | self.bank1 & ((1 << bits) - 1)
+    }
+
+    fn ram_bank(&self) -> usize {
+        match self.memory_model {
+            MemoryModel::Model0 => 0,
+            MemoryModel::Model1 => self.bank2,
         }
     }
+
+    fn ram_addr(&self, addr: usize) -> usize {
+        banked_addr(&self.ram, RAM_BANK_SIZE, self.ram_bank(), addr)
+    }
 }
 
 impl MemoryBankController for Mbc1 {
     fn read(&self, addr: u16) -> u8 {
         let addr = addr as usize;
         match addr {
-            0x0000...0x3FFF => self.rom[addr],
-            0x4000...0x7FFF => self.rom[(addr - 0x4000) + (self.rom_bank * 0x4000)],
+            0x0000...0x3FFF => self.rom[banked_addr(&self.rom, ROM_BANK_SIZE, self.rom_bank0(), addr)],
+            0x4000...0x7FFF => self.rom[banked_addr(&self.rom, ROM_BANK_SIZE, self.rom_bank(), addr)],
             0xA000...0xBFFF => {
                 if !self.ram_enabled {
                     return 0xFF;
                 }
 
-                self.ram[(addr - 0xA000) + (self.ram_bank * 0x2000)]
+                self.ram.get(self.ram_addr(addr)).cloned().unwrap_or(0xFF)
             }
-            _ => panic!("inaccessible address"),
+            _ => 0xFF,
         }
     }
 
@@ -54,17 +102,15 @@ impl MemoryBankController for Mbc1 {
                 0x0A => self.ram_enabled = true,
                 _ => (),
             },
+            // Bank 0 is replaced with bank 1 before the upper bits are added, so banks 0x20, 0x40 and 0x60
+            // cannot be mapped to 0x4000...0x7FFF
             0x2000...0x3FFF => {
-                self.rom_bank = (data & 0x1F) as usize | (self.rom_bank & 0xE0);
-                self.rom_bank = increment_rom_bank(self.rom_bank);
+                self.bank1 = match data & 0x1F {
+                    0x00 => 0x01,
+                    bank => bank as usize,
+                };
             }
-            0x4000...0x5FFF => match self.memory_model {
-                MemoryModel::Model0 => {
-                    self.rom_bank = ((data & 0x03) << 5) as usize | (self.rom_bank & 0x1F);
-                    self.rom_bank = increment_rom_bank(self.rom_bank);
-                }
-                MemoryModel::Model1 => self.ram_bank = (data & 0x03) as usize,
-            },
+            0x4000...0x5FFF => self.bank2 = (data & 0x03) as usize,
             0x6000...0x7FFF => match data & 0x01 {
                 0x00 => self.memory_model = MemoryModel::Model0,
                 0x01 => self.memory_model = MemoryModel::Model1,
@@ -75,16 +121,148 @@ impl MemoryBankController for Mbc1 {
                     return;
                 }
 
-                self.ram[(addr - 0xA000) + (self.ram_bank * 0x2000)] = data;
+                let addr = self.ram_addr(addr);
+                if let Some(byte) = self.ram.get_mut(addr) {
+                    *byte = data;
+                }
             }
-            _ => panic!("inaccessible address"),
+            _ => (),
         };
     }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
 }
 
-fn increment_rom_bank(rom_bank: usize) -> usize {
-    match rom_bank {
-        0x00 | 0x20 | 0x40 | 0x60 => rom_bank + 1,
-        _ => rom_bank,
+// is_multicart returns true if the ROM is a 1MB MBC1M multicart, which is told by the games after the first one
+// having the logo at the start of their 256KB
+fn is_multicart(rom: &[u8]) -> bool {
+    if rom.len() != MULTICART_ROM_SIZE {
+        return false;
+    }
+
+    let games = (MULTICART_GAME_SIZE..rom.len())
+        .step_by(MULTICART_GAME_SIZE)
+        .filter(|&base| rom[base + LOGO_ADDR..base + LOGO_ADDR + NINTENDO_LOGO.len()] == NINTENDO_LOGO[..])
+        .count();
+    games >= 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // new_mbc1 returns an MBC1 with the ROM of the number of banks, whose banks start with their bank numbers
+    fn new_mbc1(banks: usize, ram_size: usize) -> Mbc1 {
+        let mut data = vec![0x00; ROM_BANK_SIZE * banks];
+        for bank in 0..banks {
+            data[bank * ROM_BANK_SIZE] = bank as u8;
+        }
+        Mbc1::new(data, ram_size)
+    }
+
+    fn select_rom_bank(mbc: &mut Mbc1, bank: u8) {
+        mbc.write(0x2000, bank & 0x1F);
+        mbc.write(0x4000, bank >> 5);
+    }
+
+    #[test]
+    fn test_rom_bank_wraps_around() {
+        // 32KB to 2MB
+        for &banks in &[2, 4, 8, 16, 32, 64, 128] {
+            let mut mbc = new_mbc1(banks, 0);
+
+            for bank in 0..0x80 {
+                select_rom_bank(&mut mbc, bank);
+                let expected = match bank & 0x1F {
+                    0x00 => bank | 0x01,
+                    _ => bank,
+                } as usize
+                    % banks;
+                assert_eq!(expected as u8, mbc.read(0x4000), "bank {} of {} banks", bank, banks);
+                assert_eq!(0x00, mbc.read(0x0000));
+            }
+        }
+    }
+
+    #[test]
+    fn test_model1_large_rom() {
+        let mut mbc = new_mbc1(128, 0x8000);
+        mbc.write(0x6000, 0x01);
+
+        // The upper bits switch 0x0000...0x3FFF as well
+        select_rom_bank(&mut mbc, 0x45);
+        assert_eq!(0x40, mbc.read(0x0000));
+        assert_eq!(0x45, mbc.read(0x4000));
+
+        mbc.write(0x6000, 0x00);
+        assert_eq!(0x00, mbc.read(0x0000));
+        assert_eq!(0x45, mbc.read(0x4000));
+
+        // On a 512KB ROM, the upper bits only reach the RAM
+        let mut mbc = new_mbc1(32, 0x8000);
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0x6000, 0x01);
+        select_rom_bank(&mut mbc, 0x45);
+        assert_eq!(0x00, mbc.read(0x0000));
+        assert_eq!(0x05, mbc.read(0x4000));
+
+        mbc.write(0xA000, 0x22);
+        mbc.write(0x4000, 0x00);
+        assert_eq!(0x00, mbc.read(0xA000));
+        mbc.write(0x4000, 0x02);
+        assert_eq!(0x22, mbc.read(0xA000));
+    }
+
+    #[test]
+    fn test_multicart() {
+        let mut mbc = new_mbc1(64, 0);
+        assert!(!mbc.multicart);
+
+        for base in (0..0x100000).step_by(0x40000) {
+            mbc.rom[base + LOGO_ADDR..base + LOGO_ADDR + 48].copy_from_slice(&NINTENDO_LOGO);
+        }
+        let mut mbc = Mbc1::new(mbc.rom, 0);
+        assert!(mbc.multicart);
+
+        // The upper bits select the 256KB game, with bit 4 of the lower bank ignored
+        select_rom_bank(&mut mbc, 0x22);
+        assert_eq!(0x12, mbc.read(0x4000));
+        mbc.write(0x2000, 0x10);
+        assert_eq!(0x10, mbc.read(0x4000));
+        mbc.write(0x2000, 0x00);
+        assert_eq!(0x11, mbc.read(0x4000));
+
+        assert_eq!(0x00, mbc.read(0x0000));
+        mbc.write(0x6000, 0x01);
+        assert_eq!(0x10, mbc.read(0x0000));
+        mbc.write(0x4000, 0x03);
+        assert_eq!(0x30, mbc.read(0x0000));
+        assert_eq!(0x31, mbc.read(0x4000));
+    }
+
+    #[test]
+    fn test_ram_bank_wraps_around() {
+        let mut mbc = new_mbc1(4, 0x2000);
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0x6000, 0x01);
+
+        mbc.write(0xA000, 0x11);
+        mbc.write(0x4000, 0x03);
+        assert_eq!(0x11, mbc.read(0xA000));
+
+        // 2KB RAM repeats every 0x800 bytes
+        let mut mbc = new_mbc1(4, 0x800);
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0xA801, 0x22);
+        mbc.write(0xBFFF, 0x33);
+        assert_eq!(0x22, mbc.read(0xA001));
+        assert_eq!(0x22, mbc.read(0xB801));
+        assert_eq!(0x33, mbc.read(0xA7FF));
     }
 }
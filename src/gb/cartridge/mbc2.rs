@@ -0,0 +1,145 @@
+use super::{banked_addr, MemoryBankController, ROM_BANK_SIZE};
+
+// MBC2 has a built-in RAM of 512 x 4 bits
+const RAM_SIZE: usize = 0x200;
+
+pub struct Mbc2 {
+    rom: Vec<u8>,
+    rom_bank: usize,
+    ram: Vec<u8>,
+
+    ram_enabled: bool,
+}
+
+impl Mbc2 {
+    pub fn new(data: Vec<u8>) -> Self {
+        Mbc2 {
+            rom: data,
+            rom_bank: 1,
+            ram: vec![0x00; RAM_SIZE],
+
+            ram_enabled: false,
+        }
+    }
+}
+
+impl MemoryBankController for Mbc2 {
+    fn read(&self, addr: u16) -> u8 {
+        let addr = addr as usize;
+        match addr {
+            0x0000...0x3FFF => self.rom[addr],
+            0x4000...0x7FFF => self.rom[banked_addr(&self.rom, ROM_BANK_SIZE, self.rom_bank, addr)],
+            0xA000...0xBFFF => {
+                if !self.ram_enabled {
+                    return 0xFF;
+                }
+
+                // Only the lower 4 bits exist and the upper ones always read as 1s.
+                // The RAM is mirrored over the whole area since only the lower 9 bits of the address are decoded.
+                0xF0 | self.ram[addr & (RAM_SIZE - 1)]
+            }
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        let addr = addr as usize;
+        match addr {
+            // Bit 8 of the address tells whether the write enables the RAM or selects the ROM bank
+            0x0000...0x3FFF if addr & 0x100 == 0 => self.ram_enabled = data & 0x0F == 0x0A,
+            0x0000...0x3FFF => {
+                self.rom_bank = match data & 0x0F {
+                    0x00 => 0x01,
+                    bank => bank as usize,
+                };
+            }
+            0x4000...0x7FFF => (),
+            0xA000...0xBFFF => {
+                if !self.ram_enabled {
+                    return;
+                }
+
+                self.ram[addr & (RAM_SIZE - 1)] = data & 0x0F;
+            }
+            _ => (),
+        };
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_mbc2() -> Mbc2 {
+        // Every ROM bank is filled with its own number
+        let mut data = vec![0x00; 0x4000 * 16];
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = (i / 0x4000) as u8;
+        }
+        Mbc2::new(data)
+    }
+
+    #[test]
+    fn test_rom_bank() {
+        let mut mbc = new_mbc2();
+        assert_eq!(0x01, mbc.read(0x4000));
+
+        mbc.write(0x2100, 0x0F);
+        assert_eq!(0x0F, mbc.read(0x4000));
+        mbc.write(0x0100, 0x00);
+        assert_eq!(0x01, mbc.read(0x7FFF));
+        mbc.write(0x3FFF, 0x13);
+        assert_eq!(0x03, mbc.read(0x4000));
+
+        // Writes with bit 8 of the address cleared do not touch the ROM bank
+        mbc.write(0x2000, 0x05);
+        assert_eq!(0x03, mbc.read(0x4000));
+    }
+
+    #[test]
+    fn test_ram_enable() {
+        let mut mbc = new_mbc2();
+        mbc.write(0xA000, 0x05);
+        assert_eq!(0xFF, mbc.read(0xA000));
+
+        // Writes with bit 8 of the address set do not touch the RAM
+        mbc.write(0x0100, 0x0A);
+        assert_eq!(0xFF, mbc.read(0xA000));
+
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0xA000, 0x05);
+        assert_eq!(0xF5, mbc.read(0xA000));
+
+        mbc.write(0x3EFF, 0x00);
+        assert_eq!(0xFF, mbc.read(0xA000));
+    }
+
+    #[test]
+    fn test_ram_nibbles_and_mirroring() {
+        let mut mbc = new_mbc2();
+        mbc.write(0x0000, 0x0A);
+
+        mbc.write(0xA000, 0xAB);
+        assert_eq!(0xFB, mbc.read(0xA000));
+
+        mbc.write(0xA1FF, 0x3C);
+        assert_eq!(0xFC, mbc.read(0xA1FF));
+
+        // The 512 half-bytes repeat every 0x200 bytes up to 0xBFFF
+        for base in (0xA000..0xC000).step_by(0x200) {
+            assert_eq!(0xFB, mbc.read(base));
+            assert_eq!(0xFC, mbc.read(base + 0x1FF));
+        }
+
+        mbc.write(0xBE01, 0x07);
+        assert_eq!(0xF7, mbc.read(0xA001));
+    }
+}
@@ -0,0 +1,215 @@
+use super::rtc::Rtc;
+use super::{banked_addr, MemoryBankController, RAM_BANK_SIZE, ROM_BANK_SIZE};
+
+pub struct Mbc3 {
+    rom: Vec<u8>,
+    rom_bank: usize,
+    ram: Vec<u8>,
+    ram_bank: usize,
+    rtc: Option<Rtc>,
+
+    // 0x00-0x03 selects a RAM bank and 0x08-0x0C selects a register of the RTC
+    bank_select: u8,
+    ram_enabled: bool,
+}
+
+impl Mbc3 {
+    pub fn new(data: Vec<u8>, ram_size: usize, rtc: Option<Rtc>) -> Self {
+        Mbc3 {
+            rom: data,
+            rom_bank: 1,
+            ram: vec![0x00; ram_size],
+            ram_bank: 0,
+            rtc,
+
+            bank_select: 0x00,
+            ram_enabled: false,
+        }
+    }
+
+    // rtc_reg returns the selected register of the RTC, if the cartridge has one
+    fn rtc_reg(&self) -> Option<u8> {
+        match self.bank_select {
+            0x08...0x0C if self.rtc.is_some() => Some(self.bank_select),
+            _ => None,
+        }
+    }
+}
+
+impl MemoryBankController for Mbc3 {
+    fn read(&self, addr: u16) -> u8 {
+        let addr = addr as usize;
+        match addr {
+            0x0000...0x3FFF => self.rom[addr],
+            0x4000...0x7FFF => self.rom[banked_addr(&self.rom, ROM_BANK_SIZE, self.rom_bank, addr)],
+            0xA000...0xBFFF => {
+                if !self.ram_enabled {
+                    return 0xFF;
+                }
+
+                match (self.rtc_reg(), &self.rtc) {
+                    (Some(reg), Some(rtc)) => rtc.read(reg),
+                    _ if self.bank_select <= 0x03 => self
+                        .ram
+                        .get(banked_addr(&self.ram, RAM_BANK_SIZE, self.ram_bank, addr))
+                        .cloned()
+                        .unwrap_or(0xFF),
+                    _ => 0xFF,
+                }
+            }
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        let addr = addr as usize;
+        match addr {
+            0x0000...0x1FFF => match data & 0x0F {
+                0x00 => self.ram_enabled = false,
+                0x0A => self.ram_enabled = true,
+                _ => (),
+            },
+            0x2000...0x3FFF => {
+                // Unlike MBC1, all the 7 bits are written at once and only bank 0 is replaced with bank 1
+                self.rom_bank = match data & 0x7F {
+                    0x00 => 0x01,
+                    bank => bank as usize,
+                };
+            }
+            0x4000...0x5FFF => {
+                self.bank_select = data;
+                if data <= 0x03 {
+                    self.ram_bank = data as usize;
+                }
+            }
+            0x6000...0x7FFF => {
+                if let Some(rtc) = self.rtc.as_mut() {
+                    rtc.write_latch(data);
+                }
+            }
+            0xA000...0xBFFF => {
+                if !self.ram_enabled {
+                    return;
+                }
+
+                if let Some(reg) = self.rtc_reg() {
+                    self.rtc.as_mut().unwrap().write(reg, data);
+                } else if self.bank_select <= 0x03 {
+                    let addr = banked_addr(&self.ram, RAM_BANK_SIZE, self.ram_bank, addr);
+                    if let Some(byte) = self.ram.get_mut(addr) {
+                        *byte = data;
+                    }
+                }
+            }
+            _ => (),
+        };
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn rtc_mut(&mut self) -> Option<&mut Rtc> {
+        self.rtc.as_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::rtc::tests::FakeClock;
+    use super::*;
+    use std::rc::Rc;
+
+    fn new_mbc3(rtc: Option<Rtc>) -> Mbc3 {
+        // Every ROM bank is filled with its own number
+        let mut data = vec![0x00; 0x4000 * 128];
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = (i / 0x4000) as u8;
+        }
+        Mbc3::new(data, 0x8000, rtc)
+    }
+
+    #[test]
+    fn test_rom_bank() {
+        let mut mbc = new_mbc3(None);
+        assert_eq!(0x00, mbc.read(0x0000));
+        assert_eq!(0x01, mbc.read(0x4000));
+
+        mbc.write(0x2000, 0x00);
+        assert_eq!(0x01, mbc.read(0x4000));
+        mbc.write(0x2000, 0x20);
+        assert_eq!(0x20, mbc.read(0x4000));
+        mbc.write(0x3FFF, 0x7F);
+        assert_eq!(0x7F, mbc.read(0x7FFF));
+    }
+
+    #[test]
+    fn test_bank_wraps_around() {
+        let mut data = vec![0x00; 0x4000 * 8];
+        data[0x4000 * 5] = 0x05;
+        let mut mbc = Mbc3::new(data, 0x2000, None);
+
+        mbc.write(0x2000, 0x7D);
+        assert_eq!(0x05, mbc.read(0x4000));
+
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0xA000, 0x42);
+        mbc.write(0x4000, 0x03);
+        assert_eq!(0x42, mbc.read(0xA000));
+    }
+
+    #[test]
+    fn test_ram_bank() {
+        let mut mbc = new_mbc3(None);
+        assert_eq!(0xFF, mbc.read(0xA000));
+
+        mbc.write(0x0000, 0x0A);
+        for bank in 0..4 {
+            mbc.write(0x4000, bank);
+            mbc.write(0xA000, bank + 0x10);
+        }
+        for bank in 0..4 {
+            mbc.write(0x4000, bank);
+            assert_eq!(bank + 0x10, mbc.read(0xA000));
+        }
+
+        // RTC registers do not exist without the timer
+        mbc.write(0x4000, 0x08);
+        assert_eq!(0xFF, mbc.read(0xA000));
+    }
+
+    #[test]
+    fn test_rtc() {
+        let clock = FakeClock::new();
+        let mut mbc = new_mbc3(Some(Rtc::new(Rc::new(clock.clone()))));
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0x4000, 0x00);
+        mbc.write(0xA000, 0x42);
+
+        clock.forward(3 * 60 + 30);
+        mbc.write(0x6000, 0x00);
+        mbc.write(0x6000, 0x01);
+
+        mbc.write(0x4000, 0x08);
+        assert_eq!(30, mbc.read(0xA000));
+        mbc.write(0x4000, 0x09);
+        assert_eq!(3, mbc.read(0xBFFF));
+
+        // Halt the clock and set the seconds
+        mbc.write(0x4000, 0x0C);
+        mbc.write(0xA000, 0x40);
+        mbc.write(0x4000, 0x08);
+        mbc.write(0xA000, 0x05);
+        clock.forward(60);
+        mbc.write(0x6000, 0x00);
+        mbc.write(0x6000, 0x01);
+        assert_eq!(5, mbc.read(0xA000));
+
+        mbc.write(0x4000, 0x00);
+        assert_eq!(0x42, mbc.read(0xA000));
+    }
+}
@@ -0,0 +1,179 @@
+use super::{banked_addr, MemoryBankController, RAM_BANK_SIZE, ROM_BANK_SIZE};
+
+pub struct Mbc5 {
+    rom: Vec<u8>,
+    rom_bank: usize,
+    ram: Vec<u8>,
+    ram_bank: usize,
+
+    ram_enabled: bool,
+    has_rumble: bool,
+    rumble: bool,
+}
+
+impl Mbc5 {
+    pub fn new(data: Vec<u8>, ram_size: usize, has_rumble: bool) -> Self {
+        Mbc5 {
+            rom: data,
+            rom_bank: 1,
+            ram: vec![0x00; ram_size],
+            ram_bank: 0,
+
+            ram_enabled: false,
+            has_rumble,
+            rumble: false,
+        }
+    }
+}
+
+impl MemoryBankController for Mbc5 {
+    fn read(&self, addr: u16) -> u8 {
+        let addr = addr as usize;
+        match addr {
+            0x0000...0x3FFF => self.rom[addr],
+            0x4000...0x7FFF => self.rom[banked_addr(&self.rom, ROM_BANK_SIZE, self.rom_bank, addr)],
+            0xA000...0xBFFF => {
+                if !self.ram_enabled {
+                    return 0xFF;
+                }
+
+                self.ram
+                    .get(banked_addr(&self.ram, RAM_BANK_SIZE, self.ram_bank, addr))
+                    .cloned()
+                    .unwrap_or(0xFF)
+            }
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        let addr = addr as usize;
+        match addr {
+            0x0000...0x1FFF => match data & 0x0F {
+                0x00 => self.ram_enabled = false,
+                0x0A => self.ram_enabled = true,
+                _ => (),
+            },
+            // The ROM bank has 9 bits and, unlike the other MBCs, bank 0 can be mapped to 0x4000...0x7FFF as well
+            0x2000...0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | data as usize,
+            0x3000...0x3FFF => self.rom_bank = (self.rom_bank & 0xFF) | ((data & 0x01) as usize) << 8,
+            0x4000...0x5FFF => {
+                // Carts with a rumble motor use bit 3 to drive it, which leaves 8 RAM banks
+                if self.has_rumble {
+                    self.rumble = data & 0x08 != 0;
+                    self.ram_bank = (data & 0x07) as usize;
+                } else {
+                    self.ram_bank = (data & 0x0F) as usize;
+                }
+            }
+            0x6000...0x7FFF => (),
+            0xA000...0xBFFF => {
+                if !self.ram_enabled {
+                    return;
+                }
+
+                let addr = banked_addr(&self.ram, RAM_BANK_SIZE, self.ram_bank, addr);
+                if let Some(byte) = self.ram.get_mut(addr) {
+                    *byte = data;
+                }
+            }
+            _ => (),
+        };
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn rumble(&self) -> bool {
+        self.rumble
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_mbc5(has_rumble: bool) -> Mbc5 {
+        // Every ROM bank is filled with the lower 8 bits of its number and the upper bit at the end of the bank
+        let mut data = vec![0x00; 0x4000 * 512];
+        for (i, byte) in data.iter_mut().enumerate() {
+            let bank = i / 0x4000;
+            *byte = if i % 0x4000 == 0x3FFF {
+                (bank >> 8) as u8
+            } else {
+                bank as u8
+            };
+        }
+        Mbc5::new(data, 0x20000, has_rumble)
+    }
+
+    #[test]
+    fn test_rom_bank() {
+        let mut mbc = new_mbc5(false);
+        assert_eq!(0x01, mbc.read(0x4000));
+
+        mbc.write(0x2000, 0x00);
+        assert_eq!(0x00, mbc.read(0x4000));
+        mbc.write(0x2000, 0xFF);
+        mbc.write(0x3000, 0x01);
+        assert_eq!(0xFF, mbc.read(0x4000));
+        assert_eq!(0x01, mbc.read(0x7FFF));
+
+        mbc.write(0x2000, 0x02);
+        assert_eq!(0x02, mbc.read(0x4000));
+        assert_eq!(0x01, mbc.read(0x7FFF));
+        mbc.write(0x3000, 0x00);
+        assert_eq!(0x00, mbc.read(0x7FFF));
+    }
+
+    #[test]
+    fn test_bank_wraps_around() {
+        let mut data = vec![0x00; 0x4000 * 4];
+        data[0x4000 * 3] = 0x03;
+        let mut mbc = Mbc5::new(data, 0x2000, false);
+
+        mbc.write(0x2000, 0xFF);
+        mbc.write(0x3000, 0x01);
+        assert_eq!(0x03, mbc.read(0x4000));
+
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0xA000, 0x42);
+        mbc.write(0x4000, 0x0F);
+        assert_eq!(0x42, mbc.read(0xA000));
+    }
+
+    #[test]
+    fn test_ram_bank() {
+        let mut mbc = new_mbc5(false);
+        mbc.write(0x0000, 0x0A);
+        for bank in 0..16 {
+            mbc.write(0x4000, bank);
+            mbc.write(0xA000, bank + 0x10);
+        }
+        for bank in 0..16 {
+            mbc.write(0x4000, bank);
+            assert_eq!(bank + 0x10, mbc.read(0xA000));
+        }
+        assert!(!mbc.rumble());
+    }
+
+    #[test]
+    fn test_rumble() {
+        let mut mbc = new_mbc5(true);
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0x4000, 0x01);
+        mbc.write(0xA000, 0x42);
+
+        mbc.write(0x4000, 0x09);
+        assert!(mbc.rumble());
+        assert_eq!(0x42, mbc.read(0xA000));
+
+        mbc.write(0x4000, 0x01);
+        assert!(!mbc.rumble());
+    }
+}
@@ -1,46 +1,461 @@
+mod header;
+mod huc1;
+mod huc3;
 mod mbc1;
+mod mbc2;
+mod mbc3;
+mod mbc5;
 mod no_mbc;
+mod rtc;
+mod save;
 
+pub use self::header::{CartridgeHeader, CartridgeType, CgbSupport};
+pub use self::rtc::{Clock, SystemClock};
+pub use self::save::SaveError;
+
+use self::header::compute_header_checksum;
+use self::huc1::HuC1;
+use self::huc3::HuC3;
 use self::mbc1::Mbc1;
+use self::mbc2::Mbc2;
+use self::mbc3::Mbc3;
+use self::mbc5::Mbc5;
 use self::no_mbc::NoMbc;
+use self::rtc::Rtc;
+use self::save::RtcFooter;
+use std::fmt;
+use std::rc::Rc;
+
+const ROM_BANK_SIZE: usize = 0x4000;
+const RAM_BANK_SIZE: usize = 0x2000;
+
+// Even the smallest ROM has 2 banks
+const MIN_ROM_SIZE: usize = ROM_BANK_SIZE * 2;
+
+// Error describes why a ROM image cannot be loaded as a cartridge
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    TooShort(usize),
+    HeaderChecksumMismatch { expected: u8, actual: u8 },
+    UnknownRomSize,
+    UnknownRamSize,
+    SizeMismatch { expected: usize, actual: usize },
+    UnsupportedType(CartridgeType),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::TooShort(len) => write!(f, "ROM is too short ({} bytes)", len),
+            Error::HeaderChecksumMismatch { expected, actual } => write!(
+                f,
+                "header checksum mismatch (expected 0x{:02X}, got 0x{:02X})",
+                expected, actual
+            ),
+            Error::UnknownRomSize => write!(f, "unknown ROM size in the header"),
+            Error::UnknownRamSize => write!(f, "unknown RAM size in the header"),
+            Error::SizeMismatch { expected, actual } => write!(
+                f,
+                "ROM size mismatch (header says {} bytes, got {} bytes)",
+                expected, actual
+            ),
+            Error::UnsupportedType(cartridge_type) => write!(f, "unsupported cartridge type {:?}", cartridge_type),
+        }
+    }
+}
 
-const CARTRIDGE_TYPE_ADDR: u16 = 0x0147;
+impl std::error::Error for Error {}
 
 pub struct Cartridge {
     mbc: Box<MemoryBankController>,
+    header: CartridgeHeader,
+    save_dirty: bool, // The battery backed RAM has changed since it was exported last
 }
 
 impl Cartridge {
-    pub fn new(data: Vec<u8>) -> Self {
-        if data.len() < CARTRIDGE_TYPE_ADDR as usize {
-            // TODO: Should be treated as ROM probably
-            panic!("broken cartridge");
-        }
-
-        match data[CARTRIDGE_TYPE_ADDR as usize] {
-            0x00 | 0x08 | 0x09 => Cartridge {
-                mbc: Box::new(NoMbc::new(data)),
-            },
-            0x01 | 0x02 | 0x03 => Cartridge {
-                mbc: Box::new(Mbc1::new(data)),
-            },
-            _ => {
-                // TODO: Add more MBC supports
-                panic!("unsupported cartridge type");
+    pub fn new(data: Vec<u8>) -> Result<Self, Error> {
+        Self::with_clock(data, Rc::new(SystemClock))
+    }
+
+    // with_clock returns a cartridge whose real time clock, if any, runs with the given clock
+    pub fn with_clock(data: Vec<u8>, clock: Rc<Clock>) -> Result<Self, Error> {
+        if data.len() < MIN_ROM_SIZE {
+            return Err(Error::TooShort(data.len()));
+        }
+        let header = CartridgeHeader::parse(&data).ok_or(Error::TooShort(data.len()))?;
+
+        // The boot ROM refuses to start a cartridge with a broken header, so it is likely not a ROM at all
+        if !header.header_checksum_valid {
+            return Err(Error::HeaderChecksumMismatch {
+                expected: header.header_checksum,
+                actual: compute_header_checksum(&data),
+            });
+        }
+
+        let rom_size = header.rom_size.ok_or(Error::UnknownRomSize)?;
+        let ram_size = header.ram_size.ok_or(Error::UnknownRamSize)?;
+        if data.len() != rom_size {
+            return Err(Error::SizeMismatch {
+                expected: rom_size,
+                actual: data.len(),
+            });
+        }
+
+        let mbc: Box<MemoryBankController> = match header.cartridge_type {
+            CartridgeType::RomOnly | CartridgeType::RomRam | CartridgeType::RomRamBattery => {
+                Box::new(NoMbc::new(data, ram_size))
+            }
+            CartridgeType::Mbc1 | CartridgeType::Mbc1Ram | CartridgeType::Mbc1RamBattery => {
+                Box::new(Mbc1::new(data, ram_size))
+            }
+            CartridgeType::Mbc2 | CartridgeType::Mbc2Battery => Box::new(Mbc2::new(data)),
+            CartridgeType::Mbc3TimerBattery | CartridgeType::Mbc3TimerRamBattery => {
+                Box::new(Mbc3::new(data, ram_size, Some(Rtc::new(clock))))
+            }
+            CartridgeType::Mbc3 | CartridgeType::Mbc3Ram | CartridgeType::Mbc3RamBattery => {
+                Box::new(Mbc3::new(data, ram_size, None))
+            }
+            CartridgeType::Mbc5
+            | CartridgeType::Mbc5Ram
+            | CartridgeType::Mbc5RamBattery
+            | CartridgeType::Mbc5Rumble
+            | CartridgeType::Mbc5RumbleRam
+            | CartridgeType::Mbc5RumbleRamBattery => {
+                let has_rumble = header.cartridge_type.has_rumble();
+                Box::new(Mbc5::new(data, ram_size, has_rumble))
             }
+            CartridgeType::HuC1RamBattery => Box::new(HuC1::new(data, ram_size)),
+            CartridgeType::HuC3 => Box::new(HuC3::new(data, ram_size, clock)),
+            cartridge_type => return Err(Error::UnsupportedType(cartridge_type)),
+        };
+
+        Ok(Cartridge {
+            mbc,
+            header,
+            save_dirty: false,
+        })
+    }
+
+    // empty returns a cartridge without any game, which the system holds until a game is loaded
+    pub fn empty() -> Self {
+        let data = vec![0x00; MIN_ROM_SIZE];
+        Cartridge {
+            header: CartridgeHeader::parse(&data).unwrap(),
+            mbc: Box::new(NoMbc::new(data, 0)),
+            save_dirty: false,
         }
     }
 
+    pub fn header(&self) -> &CartridgeHeader {
+        &self.header
+    }
+
+    // cgb_supported returns true if the game makes use of the CGB features
+    pub fn cgb_supported(&self) -> bool {
+        self.header.cgb_support != CgbSupport::None
+    }
+
     pub fn read(&self, addr: u16) -> u8 {
         self.mbc.read(addr)
     }
 
     pub fn write(&mut self, addr: u16, data: u8) {
-        self.mbc.write(addr, data);
+        match addr {
+            // Only the writes which change the RAM count, as the MBC ignores them while the RAM is disabled
+            0xA000...0xBFFF if self.has_battery() => {
+                let before = self.mbc.read(addr);
+                self.mbc.write(addr, data);
+                if self.mbc.read(addr) != before {
+                    self.save_dirty = true;
+                }
+            }
+            _ => self.mbc.write(addr, data),
+        }
+    }
+
+    // has_battery returns true if the cartridge keeps its RAM while the power is off
+    pub fn has_battery(&self) -> bool {
+        self.header.cartridge_type.has_battery()
+    }
+
+    // save_dirty returns true if the game has changed the battery backed RAM since the last export
+    pub fn save_dirty(&self) -> bool {
+        self.save_dirty
+    }
+
+    // export_save returns the battery backed RAM in the .sav format, or None if the cartridge has no battery.
+    // The state of the real time clock follows the RAM in the layout of VBA and BGB, or of SameBoy for HuC3
+    pub fn export_save(&mut self) -> Option<Vec<u8>> {
+        if !self.has_battery() {
+            return None;
+        }
+
+        self.save_dirty = false;
+        let mut save = self.mbc.ram().to_vec();
+        if let Some(footer) = self.mbc.footer() {
+            save.extend(footer);
+        }
+        Some(save)
+    }
+
+    // import_save restores the battery backed RAM from a .sav file, which may be followed by the state of
+    // the real time clock
+    pub fn import_save(&mut self, data: &[u8]) -> Result<(), SaveError> {
+        if !self.has_battery() {
+            return Err(SaveError::NoBattery);
+        }
+
+        let ram_size = self.mbc.ram().len();
+        if data.len() < ram_size {
+            return Err(SaveError::SizeMismatch {
+                expected: ram_size,
+                actual: data.len(),
+            });
+        }
+
+        let (ram, trailing) = data.split_at(ram_size);
+        if !trailing.is_empty() && !self.mbc.load_footer(trailing) {
+            return Err(SaveError::UnknownTrailingData(trailing.len()));
+        }
+
+        self.mbc.ram_mut().copy_from_slice(ram);
+        self.save_dirty = false;
+        Ok(())
+    }
+
+    // rumble returns true while the game turns on the rumble motor of the cartridge
+    pub fn rumble(&self) -> bool {
+        self.mbc.rumble()
+    }
+
+    // take_tone returns the tone the game has asked the speaker of the cartridge to play since the last call
+    pub fn take_tone(&mut self) -> Option<u8> {
+        self.mbc.take_tone()
     }
 }
 
+// banked_addr returns the index into the memory for the address in the bank. Bank numbers wrap around the banks
+// the memory actually has, as the upper bank lines of the MBC are left unconnected on smaller cartridges.
+// Memory smaller than a bank (2KB RAM) repeats itself over the bank for the same reason
+fn banked_addr(memory: &[u8], bank_size: usize, bank: usize, addr: usize) -> usize {
+    if memory.len() < bank_size {
+        return (addr & (bank_size - 1)) % memory.len().max(1);
+    }
+
+    let banks = memory.len() / bank_size;
+    (bank % banks) * bank_size + (addr & (bank_size - 1))
+}
+
 trait MemoryBankController {
     fn read(&self, addr: u16) -> u8;
     fn write(&mut self, addr: u16, data: u8);
+
+    // ram returns the external RAM, which the battery keeps while the power is off on some cartridges
+    fn ram(&self) -> &[u8];
+    fn ram_mut(&mut self) -> &mut [u8];
+
+    // rtc_mut returns the real time clock, if the cartridge has one
+    fn rtc_mut(&mut self) -> Option<&mut Rtc> {
+        None
+    }
+
+    // footer returns the state which follows the RAM in save files, such as the real time clock
+    fn footer(&mut self) -> Option<Vec<u8>> {
+        self.rtc_mut().map(|rtc| rtc.footer().to_bytes())
+    }
+
+    // load_footer restores the state from the footer of a save file, or returns false if the layout is unknown
+    fn load_footer(&mut self, data: &[u8]) -> bool {
+        match (RtcFooter::parse(data), self.rtc_mut()) {
+            (Some(footer), Some(rtc)) => {
+                rtc.load_footer(&footer);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn rumble(&self) -> bool {
+        false
+    }
+
+    fn take_tone(&mut self) -> Option<u8> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::save::{HUC3_FOOTER_SIZE, HUC3_MEMORY_SIZE};
+    use super::*;
+
+    // rom returns a ROM image of the size with a valid header for the cartridge type
+    fn rom(cartridge_type: u8, rom_size: u8, len: usize) -> Vec<u8> {
+        let mut data = vec![0x00; len];
+        data[0x0147] = cartridge_type;
+        data[0x0148] = rom_size;
+        data[0x014D] = compute_header_checksum(&data);
+        data
+    }
+
+    #[test]
+    fn test_new() {
+        let cart = Cartridge::new(rom(0x01, 0x01, 0x10000)).unwrap();
+        assert_eq!(CartridgeType::Mbc1, cart.header().cartridge_type);
+    }
+
+    #[test]
+    fn test_save() {
+        let mut data = rom(0x03, 0x00, 0x8000); // MBC1+RAM+BATTERY
+        data[0x0149] = 0x02;
+        data[0x014D] = compute_header_checksum(&data);
+        let mut cart = Cartridge::new(data).unwrap();
+        assert!(!cart.save_dirty());
+
+        // Writes while the RAM is disabled change nothing
+        cart.write(0xA000, 0x42);
+        assert!(!cart.save_dirty());
+
+        cart.write(0x0000, 0x0A);
+        cart.write(0xA000, 0x42);
+        assert!(cart.save_dirty());
+
+        let save = cart.export_save().unwrap();
+        assert_eq!(0x2000, save.len());
+        assert_eq!(0x42, save[0]);
+        assert!(!cart.save_dirty());
+
+        // Writing the same value does not make it dirty
+        cart.write(0xA000, 0x42);
+        assert!(!cart.save_dirty());
+
+        let mut save = vec![0x00; 0x2000];
+        save[0x1FFF] = 0x24;
+        cart.import_save(&save).unwrap();
+        assert_eq!(0x00, cart.read(0xA000));
+        assert_eq!(0x24, cart.read(0xBFFF));
+
+        assert_eq!(
+            Err(SaveError::SizeMismatch {
+                expected: 0x2000,
+                actual: 0x800
+            }),
+            cart.import_save(&[0x00; 0x800])
+        );
+
+        // Cartridges without the clock have no footer to read
+        assert_eq!(
+            Err(SaveError::UnknownTrailingData(48)),
+            cart.import_save(&[0x00; 0x2000 + 48])
+        );
+    }
+
+    #[test]
+    fn test_save_with_rtc() {
+        let mut data = rom(0x10, 0x00, 0x8000); // MBC3+TIMER+RAM+BATTERY
+        data[0x0149] = 0x02;
+        data[0x014D] = compute_header_checksum(&data);
+        let mut cart = Cartridge::new(data.clone()).unwrap();
+
+        cart.write(0x0000, 0x0A);
+        cart.write(0x4000, 0x0A);
+        cart.write(0xA000, 0x07);
+        assert!(cart.save_dirty());
+
+        let save = cart.export_save().unwrap();
+        assert_eq!(0x2000 + 48, save.len());
+        assert_eq!(0x07, save[0x2000 + 8]);
+
+        // Either of the footer layouts can be imported
+        for &len in &[0x2000 + 48, 0x2000 + 44, 0x2000] {
+            let mut cart = Cartridge::new(data.clone()).unwrap();
+            cart.import_save(&save[..len]).unwrap();
+            cart.write(0x0000, 0x0A);
+            cart.write(0x4000, 0x0A);
+            cart.write(0x6000, 0x00);
+            cart.write(0x6000, 0x01);
+            let hours = if len == 0x2000 { 0x00 } else { 0x07 };
+            assert_eq!(hours, cart.read(0xA000));
+        }
+
+        let mut cart = Cartridge::new(data).unwrap();
+        assert_eq!(
+            Err(SaveError::UnknownTrailingData(16)),
+            cart.import_save(&[0x00; 0x2000 + 16])
+        );
+    }
+
+    #[test]
+    fn test_save_with_huc3() {
+        let mut data = rom(0xFE, 0x00, 0x8000); // HuC3
+        data[0x0149] = 0x02;
+        data[0x014D] = compute_header_checksum(&data);
+        let mut cart = Cartridge::new(data.clone()).unwrap();
+
+        let save = cart.export_save().unwrap();
+        assert_eq!(0x2000 + HUC3_FOOTER_SIZE + HUC3_MEMORY_SIZE, save.len());
+
+        // The clock alone from other emulators can be imported, but not the footer of MBC3
+        for &len in &[save.len(), 0x2000 + HUC3_FOOTER_SIZE, 0x2000] {
+            let mut cart = Cartridge::new(data.clone()).unwrap();
+            assert_eq!(Ok(()), cart.import_save(&save[..len]));
+        }
+        assert_eq!(
+            Err(SaveError::UnknownTrailingData(48)),
+            cart.import_save(&[0x00; 0x2000 + 48])
+        );
+    }
+
+    #[test]
+    fn test_save_without_battery() {
+        let mut data = rom(0x02, 0x00, 0x8000); // MBC1+RAM
+        data[0x0149] = 0x02;
+        data[0x014D] = compute_header_checksum(&data);
+        let mut cart = Cartridge::new(data).unwrap();
+
+        cart.write(0x0000, 0x0A);
+        cart.write(0xA000, 0x42);
+        assert!(!cart.save_dirty());
+        assert_eq!(None, cart.export_save());
+        assert_eq!(Err(SaveError::NoBattery), cart.import_save(&[0x00; 0x2000]));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(Some(Error::TooShort(0x100)), Cartridge::new(vec![0x00; 0x100]).err());
+        assert_eq!(Some(Error::TooShort(0x7FFF)), Cartridge::new(vec![0x00; 0x7FFF]).err());
+
+        let mut data = rom(0x00, 0x00, 0x8000);
+        data[0x0134] = b'A';
+        assert_eq!(
+            Some(Error::HeaderChecksumMismatch {
+                expected: 0xE7,
+                actual: 0xA6
+            }),
+            Cartridge::new(data).err()
+        );
+
+        assert_eq!(
+            Some(Error::UnknownRomSize),
+            Cartridge::new(rom(0x00, 0x20, 0x8000)).err()
+        );
+
+        let mut data = rom(0x00, 0x00, 0x8000);
+        data[0x0149] = 0x06;
+        data[0x014D] = compute_header_checksum(&data);
+        assert_eq!(Some(Error::UnknownRamSize), Cartridge::new(data).err());
+
+        assert_eq!(
+            Some(Error::SizeMismatch {
+                expected: 0x10000,
+                actual: 0x8000
+            }),
+            Cartridge::new(rom(0x01, 0x01, 0x8000)).err()
+        );
+
+        assert_eq!(
+            Some(Error::UnsupportedType(CartridgeType::Mbc6)),
+            Cartridge::new(rom(0x20, 0x00, 0x8000)).err()
+        );
+    }
 }
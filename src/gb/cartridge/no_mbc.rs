@@ -1,4 +1,4 @@
-use super::MemoryBankController;
+use super::{banked_addr, MemoryBankController, RAM_BANK_SIZE};
 
 pub struct NoMbc {
     rom: Vec<u8>,
@@ -6,10 +6,10 @@ pub struct NoMbc {
 }
 
 impl NoMbc {
-    pub fn new(data: Vec<u8>) -> Self {
+    pub fn new(data: Vec<u8>, ram_size: usize) -> Self {
         NoMbc {
             rom: data,
-            ram: vec![0x00; 0x2000],
+            ram: vec![0x00; ram_size],
         }
     }
 }
@@ -19,16 +19,54 @@ impl MemoryBankController for NoMbc {
         let addr = addr as usize;
         match addr {
             0x0000...0x7FFF => self.rom[addr],
-            0xA000...0xBFFF => self.ram[addr - 0xA000],
-            _ => panic!("inaccessible address"),
+            0xA000...0xBFFF => {
+                let addr = banked_addr(&self.ram, RAM_BANK_SIZE, 0, addr);
+                self.ram.get(addr).cloned().unwrap_or(0xFF)
+            }
+            _ => 0xFF,
         }
     }
 
     fn write(&mut self, addr: u16, data: u8) {
         let addr = addr as usize;
         match addr {
-            0xA000...0xBFFF => self.ram[addr - 0xA000] = data,
-            _ => { /* TODO: Consider if this case should be error */ }
+            0xA000...0xBFFF => {
+                let addr = banked_addr(&self.ram, RAM_BANK_SIZE, 0, addr);
+                if let Some(byte) = self.ram.get_mut(addr) {
+                    *byte = data;
+                }
+            }
+            _ => (),
         };
     }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ram_mirror() {
+        let mut mbc = NoMbc::new(vec![0x00; 0x8000], 0x800);
+
+        // 2KB RAM repeats every 0x800 bytes
+        mbc.write(0xA801, 0x22);
+        mbc.write(0xBFFF, 0x33);
+        assert_eq!(0x22, mbc.read(0xA001));
+        assert_eq!(0x22, mbc.read(0xB801));
+        assert_eq!(0x33, mbc.read(0xA7FF));
+
+        // Without RAM, reads return 0xFF
+        let mut mbc = NoMbc::new(vec![0x00; 0x8000], 0);
+        mbc.write(0xA000, 0x11);
+        assert_eq!(0xFF, mbc.read(0xA000));
+    }
 }
@@ -0,0 +1,287 @@
+use super::save::RtcFooter;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Clock is the source of time which the real time clock of a cartridge keeps running with
+pub trait Clock {
+    // now returns the current time in seconds since the UNIX epoch, which save files of other emulators rely on
+    fn now(&self) -> u64;
+}
+
+// SystemClock reads the time of the host
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+struct Registers {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    days: u16, // 9 bits
+    halted: bool,
+    day_carry: bool,
+}
+
+impl Registers {
+    fn read(&self, reg: u8) -> u8 {
+        match reg {
+            0x08 => self.seconds,
+            0x09 => self.minutes,
+            0x0A => self.hours,
+            0x0B => self.days as u8,
+            0x0C => (self.day_carry as u8) << 7 | (self.halted as u8) << 6 | (self.days >> 8) as u8,
+            _ => unreachable!(),
+        }
+    }
+
+    fn write(&mut self, reg: u8, data: u8) {
+        match reg {
+            0x08 => self.seconds = data & 0x3F,
+            0x09 => self.minutes = data & 0x3F,
+            0x0A => self.hours = data & 0x1F,
+            0x0B => self.days = (self.days & 0x100) | data as u16,
+            0x0C => {
+                self.days = (self.days & 0xFF) | ((data & 0x01) as u16) << 8;
+                self.halted = data & 0x40 != 0;
+                self.day_carry = data & 0x80 != 0;
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn advance(&mut self, secs: u64) {
+        let secs = self.seconds as u64 + secs;
+        self.seconds = (secs % 60) as u8;
+
+        let mins = self.minutes as u64 + secs / 60;
+        self.minutes = (mins % 60) as u8;
+
+        let hours = self.hours as u64 + mins / 60;
+        self.hours = (hours % 24) as u8;
+
+        // The day counter has 9 bits, and the carry bit stays set once it overflows until it is cleared
+        let days = self.days as u64 + hours / 24;
+        self.days = (days % 512) as u16;
+        if days >= 512 {
+            self.day_carry = true;
+        }
+    }
+}
+
+// Rtc is the real time clock of cartridges such as MBC3, which keeps counting while the game is not running
+pub struct Rtc {
+    clock: Rc<Clock>,
+    last_update: u64,
+
+    regs: Registers,
+    latched: Registers, // The registers read from the CPU, which are a copy of the clock at the latch
+
+    latch_prepared: bool,
+}
+
+impl Rtc {
+    pub fn new(clock: Rc<Clock>) -> Self {
+        let now = clock.now();
+
+        Rtc {
+            clock,
+            last_update: now,
+
+            regs: Registers::default(),
+            latched: Registers::default(),
+
+            latch_prepared: false,
+        }
+    }
+
+    // write_latch latches the current time into the registers on the write of 0x00 followed by 0x01
+    pub fn write_latch(&mut self, data: u8) {
+        if self.latch_prepared && data == 0x01 {
+            self.update();
+            self.latched = self.regs;
+        }
+        self.latch_prepared = data == 0x00;
+    }
+
+    pub fn read(&self, reg: u8) -> u8 {
+        self.latched.read(reg)
+    }
+
+    pub fn write(&mut self, reg: u8, data: u8) {
+        self.update();
+        self.regs.write(reg, data);
+        // The written value can be read back without latching
+        self.latched.write(reg, data);
+    }
+
+    // footer returns the state of the clock to be appended to save files
+    pub fn footer(&mut self) -> RtcFooter {
+        self.update();
+
+        let mut footer = RtcFooter {
+            timestamp: self.last_update,
+            ..RtcFooter::default()
+        };
+        for (i, reg) in (0x08..=0x0C).enumerate() {
+            footer.regs[i] = self.regs.read(reg);
+            footer.latched[i] = self.latched.read(reg);
+        }
+        footer
+    }
+
+    // load_footer restores the state of the clock from a save file, catching up with the time passed since
+    pub fn load_footer(&mut self, footer: &RtcFooter) {
+        for (i, reg) in (0x08..=0x0C).enumerate() {
+            self.regs.write(reg, footer.regs[i]);
+            self.latched.write(reg, footer.latched[i]);
+        }
+        self.last_update = footer.timestamp;
+        self.update();
+    }
+
+    fn update(&mut self) {
+        let now = self.clock.now();
+        let elapsed = now.saturating_sub(self.last_update);
+        self.last_update = now;
+
+        if !self.regs.halted {
+            self.regs.advance(elapsed);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    // FakeClock is a clock which tests move forward by hand
+    #[derive(Clone)]
+    pub struct FakeClock(Rc<Cell<u64>>);
+
+    impl FakeClock {
+        pub fn new() -> Self {
+            FakeClock(Rc::new(Cell::new(1_000_000)))
+        }
+
+        pub fn forward(&self, secs: u64) {
+            self.0.set(self.0.get() + secs);
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    fn latch(rtc: &mut Rtc) {
+        rtc.write_latch(0x00);
+        rtc.write_latch(0x01);
+    }
+
+    #[test]
+    fn test_latch() {
+        let clock = FakeClock::new();
+        let mut rtc = Rtc::new(Rc::new(clock.clone()));
+
+        clock.forward(1 + 2 * 60 + 3 * 3600 + 4 * 86400);
+        assert_eq!(0, rtc.read(0x08));
+
+        latch(&mut rtc);
+        assert_eq!(1, rtc.read(0x08));
+        assert_eq!(2, rtc.read(0x09));
+        assert_eq!(3, rtc.read(0x0A));
+        assert_eq!(4, rtc.read(0x0B));
+        assert_eq!(0, rtc.read(0x0C));
+
+        // The registers keep the latched time until the next latch
+        clock.forward(10);
+        assert_eq!(1, rtc.read(0x08));
+        rtc.write_latch(0x01);
+        assert_eq!(1, rtc.read(0x08));
+        latch(&mut rtc);
+        assert_eq!(11, rtc.read(0x08));
+    }
+
+    #[test]
+    fn test_halt() {
+        let clock = FakeClock::new();
+        let mut rtc = Rtc::new(Rc::new(clock.clone()));
+
+        clock.forward(5);
+        rtc.write(0x0C, 0x40);
+        clock.forward(100);
+        latch(&mut rtc);
+        assert_eq!(5, rtc.read(0x08));
+        assert_eq!(0x40, rtc.read(0x0C));
+
+        rtc.write(0x0C, 0x00);
+        clock.forward(7);
+        latch(&mut rtc);
+        assert_eq!(12, rtc.read(0x08));
+    }
+
+    #[test]
+    fn test_footer() {
+        let clock = FakeClock::new();
+        let mut rtc = Rtc::new(Rc::new(clock.clone()));
+        rtc.write(0x0A, 5);
+        clock.forward(30);
+        latch(&mut rtc);
+        clock.forward(10);
+
+        let footer = rtc.footer();
+        assert_eq!([40, 0, 5, 0, 0], footer.regs);
+        assert_eq!([30, 0, 5, 0, 0], footer.latched);
+        assert_eq!(clock.now(), footer.timestamp);
+
+        // The clock keeps counting while the game is not running
+        clock.forward(60);
+        let mut rtc = Rtc::new(Rc::new(clock.clone()));
+        rtc.load_footer(&footer);
+        assert_eq!(30, rtc.read(0x08));
+        latch(&mut rtc);
+        assert_eq!(40, rtc.read(0x08));
+        assert_eq!(1, rtc.read(0x09));
+        assert_eq!(5, rtc.read(0x0A));
+    }
+
+    #[test]
+    fn test_day_carry() {
+        let clock = FakeClock::new();
+        let mut rtc = Rtc::new(Rc::new(clock.clone()));
+
+        rtc.write(0x0B, 0xFF);
+        rtc.write(0x0C, 0x01);
+        rtc.write(0x0A, 23);
+        rtc.write(0x09, 59);
+        rtc.write(0x08, 59);
+
+        clock.forward(1);
+        latch(&mut rtc);
+        assert_eq!(0, rtc.read(0x08));
+        assert_eq!(0, rtc.read(0x09));
+        assert_eq!(0, rtc.read(0x0A));
+        assert_eq!(0, rtc.read(0x0B));
+        assert_eq!(0x80, rtc.read(0x0C));
+
+        // The carry bit stays set until it is cleared by a write
+        clock.forward(86400);
+        latch(&mut rtc);
+        assert_eq!(1, rtc.read(0x0B));
+        assert_eq!(0x80, rtc.read(0x0C));
+
+        rtc.write(0x0C, 0x00);
+        assert_eq!(0x00, rtc.read(0x0C));
+    }
+}
@@ -0,0 +1,175 @@
+use std::fmt;
+
+// VBA and BGB append the state of the real time clock to save files in 48 bytes, or in 44 bytes with
+// a 32-bit timestamp in older versions
+pub const RTC_FOOTER_SIZE: usize = 48;
+const RTC_FOOTER_SIZE_32: usize = 44;
+
+// The clock of HuC3 is saved in the 17 bytes of SameBoy: the timestamp, the minutes and days, and the alarm
+pub const HUC3_FOOTER_SIZE: usize = 17;
+
+// The nibbles of the HuC3 chip follow the clock in the saves of this emulator, which is an extension no other
+// emulator knows about. SameBoy reads the clock and ignores them
+pub const HUC3_MEMORY_SIZE: usize = 0x100;
+
+// SaveError describes why a save file cannot be imported into the cartridge
+#[derive(Debug, PartialEq)]
+pub enum SaveError {
+    NoBattery,
+    SizeMismatch { expected: usize, actual: usize },
+    UnknownTrailingData(usize),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveError::NoBattery => write!(f, "the cartridge has no battery to keep a save"),
+            SaveError::SizeMismatch { expected, actual } => write!(
+                f,
+                "save size mismatch (the cartridge has {} bytes of RAM, got {} bytes)",
+                expected, actual
+            ),
+            SaveError::UnknownTrailingData(len) => write!(f, "unknown {} bytes after the RAM in the save", len),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {}
+
+// RtcFooter is the state of the real time clock in save files, which holds the values of the registers
+// (seconds, minutes, hours, the lower 8 bits of days and the control) and the time of the save
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct RtcFooter {
+    pub regs: [u8; 5],
+    pub latched: [u8; 5],
+    pub timestamp: u64,
+}
+
+impl RtcFooter {
+    // parse reads the footer in either of the layouts, where every register takes 4 bytes in little endian
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() != RTC_FOOTER_SIZE && data.len() != RTC_FOOTER_SIZE_32 {
+            return None;
+        }
+
+        let mut footer = RtcFooter::default();
+        for i in 0..5 {
+            footer.regs[i] = data[i * 4];
+            footer.latched[i] = data[20 + i * 4];
+        }
+        footer.timestamp = data[40..]
+            .iter()
+            .rev()
+            .fold(0u64, |timestamp, &b| timestamp << 8 | b as u64);
+        Some(footer)
+    }
+
+    // to_bytes returns the footer in the 48-byte layout
+    pub fn to_bytes(self) -> Vec<u8> {
+        let mut data = vec![0x00; RTC_FOOTER_SIZE];
+        for i in 0..5 {
+            data[i * 4] = self.regs[i];
+            data[20 + i * 4] = self.latched[i];
+        }
+        data[40..48].copy_from_slice(&self.timestamp.to_le_bytes());
+        data
+    }
+}
+
+// HuC3Footer is the state of the clock of HuC3 in save files, where the minutes and days are those at the time
+// of the save
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct HuC3Footer {
+    pub timestamp: u64,
+    pub minutes: u16,
+    pub days: u16,
+    pub alarm_minutes: u16,
+    pub alarm_days: u16,
+    pub alarm_enabled: bool,
+}
+
+impl HuC3Footer {
+    // parse reads the footer, where the values are in little endian
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() != HUC3_FOOTER_SIZE {
+            return None;
+        }
+
+        let mut timestamp = [0x00; 8];
+        timestamp.copy_from_slice(&data[0..8]);
+        let u16_at = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+        Some(HuC3Footer {
+            timestamp: u64::from_le_bytes(timestamp),
+            minutes: u16_at(8),
+            days: u16_at(10),
+            alarm_minutes: u16_at(12),
+            alarm_days: u16_at(14),
+            alarm_enabled: data[16] & 0x01 != 0,
+        })
+    }
+
+    // to_bytes returns the footer in the layout of SameBoy
+    pub fn to_bytes(self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(HUC3_FOOTER_SIZE);
+        data.extend_from_slice(&self.timestamp.to_le_bytes());
+        for value in &[self.minutes, self.days, self.alarm_minutes, self.alarm_days] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.push(self.alarm_enabled as u8);
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_footer_layouts() {
+        let footer = RtcFooter {
+            regs: [1, 2, 3, 4, 0xC1],
+            latched: [5, 6, 7, 8, 0x00],
+            timestamp: 0x1_2345_6789,
+        };
+
+        let data = footer.to_bytes();
+        assert_eq!(RTC_FOOTER_SIZE, data.len());
+        assert_eq!(&[0x01, 0x00, 0x00, 0x00, 0x02], &data[0..5]);
+        assert_eq!(&[0x89, 0x67, 0x45, 0x23, 0x01, 0x00, 0x00, 0x00], &data[40..]);
+        assert_eq!(Some(footer), RtcFooter::parse(&data));
+
+        // The 44-byte layout cannot hold timestamps beyond 32 bits
+        let short = RtcFooter {
+            timestamp: 0x2345_6789,
+            ..footer
+        };
+        assert_eq!(Some(short), RtcFooter::parse(&data[..RTC_FOOTER_SIZE_32]));
+
+        assert_eq!(None, RtcFooter::parse(&data[..40]));
+    }
+
+    #[test]
+    fn test_huc3_footer() {
+        let footer = HuC3Footer {
+            timestamp: 0x1_2345_6789,
+            minutes: 1439,
+            days: 0x1234,
+            alarm_minutes: 0x0123,
+            alarm_days: 0x0456,
+            alarm_enabled: true,
+        };
+
+        let data = footer.to_bytes();
+        assert_eq!(
+            vec![
+                0x89, 0x67, 0x45, 0x23, 0x01, 0x00, 0x00, 0x00, // Timestamp
+                0x9F, 0x05, 0x34, 0x12, // Minutes and days
+                0x23, 0x01, 0x56, 0x04, 0x01, // Alarm
+            ],
+            data
+        );
+        assert_eq!(Some(footer), HuC3Footer::parse(&data));
+
+        assert_eq!(None, HuC3Footer::parse(&data[..16]));
+    }
+}
@@ -210,7 +210,7 @@ pub fn exec<B: Bus>(opcode: u8, state: &mut State, bus: &mut B) -> (u8, u8) {
         0xC8 => p.ret(Condition::Z).r(0, 8),                 // [RET Z] [1  20/8] [- - - -]
         0xC9 => p.ret(Condition::T).r(0, 4),                 // [RET] [1  16] [- - - -]
         0xCA => p.jp(Condition::Z, Immediate16).r(0, 12),    // [JP Z,a16] [3  16/12] [- - - -]
-        0xCB => p.undefined(opcode).r(1, 0),                 // [PREFIX CB] [1  4] [- - - -]
+        0xCB => p.undefined(opcode).r(0, 4),                 // [PREFIX CB] [1  4] [- - - -]
         0xCC => p.call(Condition::Z, Immediate16).r(0, 12),  // [CALL Z,a16] [3  24/12] [- - - -]
         0xCD => p.call(Condition::T, Immediate16).r(0, 12),  // [CALL a16] [3  24] [- - - -]
         0xCE => p.adc8(Immediate8).r(2, 8),                  // [ADC A,d8] [2  8] [Z 0 H C]
@@ -218,7 +218,7 @@ pub fn exec<B: Bus>(opcode: u8, state: &mut State, bus: &mut B) -> (u8, u8) {
         0xD0 => p.ret(Condition::NC).r(0, 8),                // [RET NC] [1  20/8] [- - - -]
         0xD1 => p.pop16(R16::DE).r(1, 12),                   // [POP DE] [1  12] [- - - -]
         0xD2 => p.jp(Condition::NC, Immediate16).r(0, 12),   // [JP NC,a16] [3  16/12] [- - - -]
-        0xD3 => p.undefined(opcode).r(1, 0),                 // [Undefined]
+        0xD3 => p.undefined(opcode).r(0, 4),                 // [Undefined]
         0xD4 => p.call(Condition::NC, Immediate16).r(0, 12), // [CALL NC,a16] [3  24/12] [- - - -]
         0xD5 => p.push16(R16::DE).r(1, 16),                  // [PUSH DE] [1  16] [- - - -]
         0xD6 => p.sub8(Immediate8).r(2, 8),                  // [SUB A,d8] [2  8] [Z 1 H C]
@@ -226,32 +226,32 @@ pub fn exec<B: Bus>(opcode: u8, state: &mut State, bus: &mut B) -> (u8, u8) {
         0xD8 => p.ret(Condition::C).r(0, 8),                 // [RET C] [1  20/8] [- - - -]
         0xD9 => p.reti().r(0, 4),                            // [RETI] [1  16] [- - - -]
         0xDA => p.jp(Condition::C, Immediate16).r(0, 12),    // [JP C,a16] [3  16/12] [- - - -]
-        0xDB => p.undefined(opcode).r(1, 0),                 // [Undefined]
+        0xDB => p.undefined(opcode).r(0, 4),                 // [Undefined]
         0xDC => p.call(Condition::C, Immediate16).r(0, 12),  // [CALL C,a16] [3  24/12] [- - - -]
-        0xDD => p.undefined(opcode).r(1, 0),                 // [Undefined]
+        0xDD => p.undefined(opcode).r(0, 4),                 // [Undefined]
         0xDE => p.sbc8(Immediate8).r(2, 8),                  // [SBC A,d8] [2  8] [Z 1 H C]
         0xDF => p.rst(0x18).r(0, 16),                        // [RST 18H] [1  16] [- - - -]
         0xE0 => p.ld8(Address::FF00, R8::A).r(2, 12),        // [LDH (a8),A] [2  12] [- - - -]
         0xE1 => p.pop16(R16::HL).r(1, 12),                   // [POP HL] [1  12] [- - - -]
         0xE2 => p.ld8(Address::FF00C, R8::A).r(1, 8),        // [LDH (C),A] [1  8] [- - - -]
-        0xE3 => p.undefined(opcode).r(1, 0),                 // [Undefined]
-        0xE4 => p.undefined(opcode).r(1, 0),                 // [Undefined]
+        0xE3 => p.undefined(opcode).r(0, 4),                 // [Undefined]
+        0xE4 => p.undefined(opcode).r(0, 4),                 // [Undefined]
         0xE5 => p.push16(R16::HL).r(1, 16),                  // [PUSH HL] [1  16] [- - - -]
         0xE6 => p.and8(Immediate8).r(2, 8),                  // [AND d8] [2  8] [Z 0 1 0]
         0xE7 => p.rst(0x20).r(0, 16),                        // [RST 20H] [1  16] [- - - -]
         0xE8 => p.add_sp_e8().r(2, 16),                      // [ADD SP,r8] [2  16] [0 0 H C]
         0xE9 => p.jp(Condition::T, R16::HL).r(0, 0),         // [JP (HL)] [1  4] [- - - -]
         0xEA => p.ld8(Address::Direct, R8::A).r(3, 16),      // [LD (a16),A] [3  16] [- - - -]
-        0xEB => p.undefined(opcode).r(1, 0),                 // [Undefined]
-        0xEC => p.undefined(opcode).r(1, 0),                 // [Undefined]
-        0xED => p.undefined(opcode).r(1, 0),                 // [Undefined]
+        0xEB => p.undefined(opcode).r(0, 4),                 // [Undefined]
+        0xEC => p.undefined(opcode).r(0, 4),                 // [Undefined]
+        0xED => p.undefined(opcode).r(0, 4),                 // [Undefined]
         0xEE => p.xor8(Immediate8).r(2, 8),                  // [XOR d8] [2  8] [Z 0 0 0]
         0xEF => p.rst(0x28).r(0, 16),                        // [RST 28H] [1  16] [- - - -]
         0xF0 => p.ld8(R8::A, Address::FF00).r(2, 12),        // [LDH A,(a8)] [2  12] [- - - -]
         0xF1 => p.pop16(R16::AF).r(1, 12),                   // [POP AF] [1  12] [- - - -]
         0xF2 => p.ld8(R8::A, Address::FF00C).r(1, 8),        // [LDH A,(C)] [1  8] [- - - -]
         0xF3 => p.di().r(1, 4),                              // [DI] [1  4] [- - - -]
-        0xF4 => p.undefined(opcode).r(1, 0),                 // [Undefined]
+        0xF4 => p.undefined(opcode).r(0, 4),                 // [Undefined]
         0xF5 => p.push16(R16::AF).r(1, 16),                  // [PUSH AF] [1  16] [- - - -]
         0xF6 => p.or8(Immediate8).r(2, 8),                   // [OR d8] [2  8] [Z 0 0 0]
         0xF7 => p.rst(0x30).r(0, 16),                        // [RST 30H] [1  16] [- - - -]
@@ -259,11 +259,11 @@ pub fn exec<B: Bus>(opcode: u8, state: &mut State, bus: &mut B) -> (u8, u8) {
         0xF9 => p.ld16(R16::SP, R16::HL).r(1, 8),            // [LD SP,HL] [1  8] [- - - -]
         0xFA => p.ld8(R8::A, Address::Direct).r(3, 16),      // [LD A,(a16)] [3  16] [- - - -]
         0xFB => p.ei().r(1, 4),                              // [EI] [1  4] [- - - -]
-        0xFC => p.undefined(opcode).r(1, 0),                 // [Undefined]
-        0xFD => p.undefined(opcode).r(1, 0),                 // [Undefined]
+        0xFC => p.undefined(opcode).r(0, 4),                 // [Undefined]
+        0xFD => p.undefined(opcode).r(0, 4),                 // [Undefined]
         0xFE => p.cp8(Immediate8).r(2, 8),                   // [CP d8] [2  8] [Z 1 H C]
         0xFF => p.rst(0x38).r(0, 16),                        // [RST 38H] [1  16] [- - - -]
-        _ => p.undefined(opcode).r(1, 0),
+        _ => p.undefined(opcode).r(0, 4),
     }
 }
 
@@ -527,15 +527,13 @@ pub fn exec_prefix_cb<B: Bus>(opcode: u8, state: &mut State, bus: &mut B) -> (u8
         0xFD => p.set8(7, R8::L).r(2, 8),        // [SET 7,L] [2  8] [- - - -]
         0xFE => p.set8(7, Address::HL).r(2, 16), // [SET 7,(HL)] [2  16] [- - - -]
         0xFF => p.set8(7, R8::A).r(2, 8),        // [SET 7,A] [2  8] [- - - -]
-        _ => p.undefined(opcode).r(1, 0),
+        _ => p.undefined(opcode).r(0, 4),
     }
 }
 
-pub fn interrupt<B: Bus>(handler_addr: u16, state: &mut State, bus: &mut B) -> u8 {
+pub fn interrupt<B: Bus>(state: &mut State, bus: &mut B) -> u8 {
     let mut p = Processor::new(state, bus);
 
-    p.push16(R16::PC);
-    state.PC = handler_addr;
-
-    20 // Cycles
+    let (_, cycles) = p.dispatch().r(0, 20); // 5 M-cycles
+    cycles
 }
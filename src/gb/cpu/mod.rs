@@ -5,21 +5,39 @@ mod processor;
 mod state;
 
 use self::instruction::{exec, exec_prefix_cb, interrupt};
+pub use self::state::LockUp;
+
 use self::state::State;
 use super::bus::Bus;
-use super::interrupt::{self, Interrupt};
+use super::interrupt;
+use super::model::CpuRegisters;
 use std::fmt;
 
+const P1_REG_ADDR: u16 = 0xFF00;
+
 pub struct Cpu {
     state: State,
+    panic_on_lock_up: bool, // Panics on illegal opcodes instead of hanging, to debug the emulator
 }
 
 impl Cpu {
     pub fn new() -> Self {
-        Cpu { state: State::new() }
+        Cpu {
+            state: State::new(),
+            panic_on_lock_up: false,
+        }
     }
 
     pub fn step<B: Bus>(&mut self, bus: &mut B) -> u8 {
+        // Nothing but a reset brings the CPU back after an illegal opcode
+        if self.state.lock_up.is_some() {
+            return 4;
+        }
+
+        if self.process_stop(bus) {
+            return 4;
+        }
+
         self.process_halt(bus);
         self.process_interrupt(bus) + self.process_instruction(bus)
     }
@@ -32,6 +50,15 @@ impl Cpu {
         let addr = self.state.PC;
         let opcode = bus.read8(addr);
 
+        // Operands are read from the opcode itself when PC has not been incremented due to the HALT bug
+        if self.state.halt_bug {
+            self.state.halt_bug = false;
+            self.state.PC = addr.wrapping_sub(1);
+        }
+
+        // IME set by EI takes effect after the instruction following EI, which still runs with IME as it was
+        let ime_scheduled = self.state.ime_next;
+
         let (bytes, cycles) = if opcode != 0xCB {
             // 1-byte instruction
             exec(opcode, &mut self.state, bus)
@@ -43,53 +70,100 @@ impl Cpu {
             exec_prefix_cb(opcode, &mut self.state, bus)
         };
 
+        if let (Some(lock_up), true) = (self.state.lock_up, self.panic_on_lock_up) {
+            panic!("{}", lock_up);
+        }
+
+        // DI in between cancels the scheduled IME
+        if ime_scheduled && self.state.ime_next {
+            self.state.ime_next = false;
+            self.state.ime = true;
+        }
+
         self.state.PC = self.state.PC.wrapping_add(bytes as u16);
         cycles
     }
 
+    // is_stopped returns true while the CPU waits for a button to be pressed after STOP
+    pub fn is_stopped(&self) -> bool {
+        self.state.stopped
+    }
+
+    // lock_up returns the illegal opcode which has hung the CPU, if any
+    pub fn lock_up(&self) -> Option<LockUp> {
+        self.state.lock_up
+    }
+
+    // set_panic_on_lock_up makes illegal opcodes panic instead of hanging the CPU
+    pub fn set_panic_on_lock_up(&mut self, enabled: bool) {
+        self.panic_on_lock_up = enabled;
+    }
+
+    // stall pauses the CPU for the given cycles, while the other hardware keeps running
+    pub fn stall(&mut self, cycles: u16) {
+        self.state.stall_cycles += cycles;
+    }
+
+    // process_stop returns true while the CPU is stopped or stalled
+    fn process_stop<B: Bus>(&mut self, bus: &mut B) -> bool {
+        if self.state.stall_cycles > 0 {
+            self.state.stall_cycles = self.state.stall_cycles.saturating_sub(4);
+            return true;
+        }
+
+        if !self.state.stopped {
+            return false;
+        }
+
+        // Pressing a button on the selected lines of P1 wakes the CPU up
+        if bus.read8(P1_REG_ADDR) & 0x0F != 0x0F {
+            self.state.stopped = false;
+            return false;
+        }
+        true
+    }
+
+    // process_halt wakes the CPU up once an enabled interrupt is requested, which is dispatched only if IME is set
     fn process_halt<B: Bus>(&mut self, bus: &mut B) {
         if !self.state.halted {
             return;
         }
 
-        if self.state.interrupts_before_halt != interrupt::dump_raw_flags(bus) {
+        if interrupt::pending(bus) {
             self.state.halted = false;
         }
     }
 
+    // process_interrupt dispatches a pending interrupt if IME is set, which is checked before each instruction
     fn process_interrupt<B: Bus>(&mut self, bus: &mut B) -> u8 {
-        if self.state.interrupting {
-            self.state.interrupting = false;
-            self.state.interrupted = true;
+        if !self.state.ime || !interrupt::pending(bus) {
             return 0;
         }
 
-        if !(self.state.interrupted || self.state.halted) {
-            return 0;
+        // The HALT bug leaves PC on HALT after EI; HALT, so the handler returns to HALT
+        if self.state.halt_bug {
+            self.state.halt_bug = false;
+            self.state.PC = self.state.PC.wrapping_sub(1);
         }
+        interrupt(&mut self.state, bus)
+    }
 
-        let pc = match interrupt::receive(bus) {
-            Interrupt::VBlank => 0x40,
-            Interrupt::LCDStat => 0x48,
-            Interrupt::Timer => 0x50,
-            Interrupt::Serial => 0x58,
-            Interrupt::Joypad => 0x60,
-
-            Interrupt::None => return 0,
-        };
-        interrupt(pc, &mut self.state, bus)
+    // reset puts the CPU into the state at power on, from which the boot ROM runs
+    pub fn reset(&mut self) {
+        self.state = State::new();
     }
 
-    pub fn simulate_bootloader(&mut self) {
+    // simulate_bootloader puts the CPU into the state the boot ROM leaves, by which games tell the hardware
+    pub fn simulate_bootloader(&mut self, regs: CpuRegisters) {
         self.state = State::new();
-        self.state.A = 0x01;
-        self.state.F = 0xB0;
-        self.state.B = 0x00;
-        self.state.C = 0x13;
-        self.state.D = 0x00;
-        self.state.E = 0xD8;
-        self.state.H = 0x01;
-        self.state.L = 0x4D;
+        self.state.A = (regs.af >> 8) as u8;
+        self.state.F = regs.af as u8 & 0xF0;
+        self.state.B = (regs.bc >> 8) as u8;
+        self.state.C = regs.bc as u8;
+        self.state.D = (regs.de >> 8) as u8;
+        self.state.E = regs.de as u8;
+        self.state.H = (regs.hl >> 8) as u8;
+        self.state.L = regs.hl as u8;
         self.state.PC = 0x0100;
         self.state.SP = 0xFFFE;
     }
@@ -109,3 +183,250 @@ impl fmt::Debug for Cpu {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::interrupt::Interrupt;
+    use super::super::ram::Ram;
+    use super::*;
+
+    const IE_REG_ADDR: u16 = 0xFFFF;
+    const IF_REG_ADDR: u16 = 0xFF0F;
+
+    const EI: u8 = 0xFB;
+    const DI: u8 = 0xF3;
+    const RETI: u8 = 0xD9;
+    const HALT: u8 = 0x76;
+    const INC_A: u8 = 0x3C;
+    const LD_A_D8: u8 = 0x3E;
+
+    // new_cpu returns a CPU which is about to run the program at 0x0100
+    fn new_cpu(program: &[u8]) -> (Cpu, Ram) {
+        let mut memory = vec![0x00; 0x10000];
+        memory[0x0100..0x0100 + program.len()].copy_from_slice(program);
+
+        let mut cpu = Cpu::new();
+        cpu.state.PC = 0x0100;
+        cpu.state.SP = 0xFFFE;
+        (cpu, Ram::new(memory))
+    }
+
+    fn run(cpu: &mut Cpu, ram: &mut Ram, steps: usize) {
+        for _ in 0..steps {
+            cpu.step(ram);
+        }
+    }
+
+    #[test]
+    fn test_halt_bug() {
+        let (mut cpu, mut ram) = new_cpu(&[HALT, INC_A, HALT]);
+        ram.write8(IE_REG_ADDR, Interrupt::Timer as u8);
+        ram.write8(IF_REG_ADDR, Interrupt::Timer as u8);
+
+        // INC A runs twice as the byte after HALT is read again
+        run(&mut cpu, &mut ram, 3);
+        assert!(!cpu.state.halted);
+        assert_eq!(0x02, cpu.state.A);
+        assert_eq!(0x0102, cpu.state.PC);
+    }
+
+    #[test]
+    fn test_halt_bug_operand() {
+        let (mut cpu, mut ram) = new_cpu(&[HALT, LD_A_D8, 0x14]);
+        ram.write8(IE_REG_ADDR, Interrupt::Timer as u8);
+        ram.write8(IF_REG_ADDR, Interrupt::Timer as u8);
+
+        // LD A,d8 takes its own opcode as the operand, then 0x14 runs as INC D
+        run(&mut cpu, &mut ram, 3);
+        assert_eq!(LD_A_D8, cpu.state.A);
+        assert_eq!(0x01, cpu.state.D);
+        assert_eq!(0x0103, cpu.state.PC);
+    }
+
+    #[test]
+    fn test_halt_wake_up_without_ime() {
+        let (mut cpu, mut ram) = new_cpu(&[HALT, INC_A]);
+        ram.write8(IE_REG_ADDR, Interrupt::Timer as u8);
+
+        run(&mut cpu, &mut ram, 3);
+        assert!(cpu.state.halted);
+        assert_eq!(0x0101, cpu.state.PC);
+
+        // Interrupts which are not enabled leave the CPU halted
+        ram.write8(IF_REG_ADDR, Interrupt::VBlank as u8);
+        run(&mut cpu, &mut ram, 1);
+        assert!(cpu.state.halted);
+
+        // The CPU resumes after HALT without dispatching the interrupt
+        ram.write8(IF_REG_ADDR, Interrupt::Timer as u8);
+        run(&mut cpu, &mut ram, 1);
+        assert!(!cpu.state.halted);
+        assert_eq!(0x01, cpu.state.A);
+        assert_eq!(0x0102, cpu.state.PC);
+        assert_eq!(Interrupt::Timer as u8, ram.read8(IF_REG_ADDR));
+    }
+
+    #[test]
+    fn test_halt_wake_up_with_ime() {
+        let (mut cpu, mut ram) = new_cpu(&[HALT, INC_A]);
+        cpu.state.ime = true;
+        ram.write8(IE_REG_ADDR, Interrupt::Timer as u8);
+
+        run(&mut cpu, &mut ram, 2);
+        assert!(cpu.state.halted);
+
+        // The handler returns to the instruction after HALT
+        ram.write8(IF_REG_ADDR, Interrupt::Timer as u8);
+        run(&mut cpu, &mut ram, 1);
+        assert_eq!(0x0051, cpu.state.PC);
+        assert_eq!(0x0101, ram.read16(0xFFFC));
+        assert_eq!(0x00, ram.read8(IF_REG_ADDR));
+    }
+
+    #[test]
+    fn test_ei_halt_bug() {
+        let (mut cpu, mut ram) = new_cpu(&[EI, HALT, INC_A]);
+        ram.write8(IE_REG_ADDR, Interrupt::Timer as u8);
+        ram.write8(IF_REG_ADDR, Interrupt::Timer as u8);
+
+        // IME is still 0 when HALT runs, so the HALT bug makes the handler return to HALT
+        run(&mut cpu, &mut ram, 3);
+        assert!(!cpu.state.halted);
+        assert!(!cpu.state.halt_bug);
+        assert_eq!(0x0051, cpu.state.PC);
+        assert_eq!(0x0101, ram.read16(0xFFFC));
+        assert_eq!(0x00, cpu.state.A);
+    }
+
+    #[test]
+    fn test_ei_delay() {
+        let (mut cpu, mut ram) = new_cpu(&[EI, INC_A, INC_A]);
+        ram.write8(IE_REG_ADDR, Interrupt::Timer as u8);
+        ram.write8(IF_REG_ADDR, Interrupt::Timer as u8);
+
+        // The instruction after EI runs before the interrupt is dispatched
+        run(&mut cpu, &mut ram, 2);
+        assert_eq!(0x01, cpu.state.A);
+        assert_eq!(20, cpu.process_interrupt(&mut ram));
+        assert_eq!(0x0050, cpu.state.PC);
+        assert_eq!(0x0102, ram.read16(0xFFFC));
+        assert!(!cpu.state.ime);
+    }
+
+    #[test]
+    fn test_ei_di() {
+        let (mut cpu, mut ram) = new_cpu(&[EI, DI, INC_A]);
+        ram.write8(IE_REG_ADDR, Interrupt::Timer as u8);
+        ram.write8(IF_REG_ADDR, Interrupt::Timer as u8);
+
+        // DI right after EI leaves no chance for the interrupt
+        run(&mut cpu, &mut ram, 3);
+        assert_eq!(0x01, cpu.state.A);
+        assert_eq!(0x0103, cpu.state.PC);
+    }
+
+    #[test]
+    fn test_ei_sequence() {
+        let (mut cpu, mut ram) = new_cpu(&[EI, EI, EI, INC_A]);
+        ram.write8(IE_REG_ADDR, Interrupt::Timer as u8);
+        ram.write8(IF_REG_ADDR, Interrupt::Timer as u8);
+
+        // The interrupt is dispatched after the second EI, as the first one has already set IME
+        run(&mut cpu, &mut ram, 2);
+        assert_eq!(20, cpu.process_interrupt(&mut ram));
+        assert_eq!(0x0102, ram.read16(0xFFFC));
+    }
+
+    #[test]
+    fn test_reti() {
+        let (mut cpu, mut ram) = new_cpu(&[RETI]);
+        cpu.state.SP = 0xFFFC;
+        ram.write16(0xFFFC, 0x0200);
+        ram.write8(IE_REG_ADDR, Interrupt::Timer as u8);
+        ram.write8(IF_REG_ADDR, Interrupt::Timer as u8);
+
+        // RETI sets IME without delay
+        run(&mut cpu, &mut ram, 1);
+        assert_eq!(0x0200, cpu.state.PC);
+        assert_eq!(20, cpu.process_interrupt(&mut ram));
+        assert_eq!(0x0050, cpu.state.PC);
+        assert_eq!(0x0200, ram.read16(0xFFFC));
+    }
+
+    #[test]
+    fn test_dispatch_priority() {
+        let (mut cpu, mut ram) = new_cpu(&[]);
+        cpu.state.ime = true;
+        ram.write8(IE_REG_ADDR, 0x1F);
+        ram.write8(IF_REG_ADDR, Interrupt::Joypad as u8 | Interrupt::LCDStat as u8);
+
+        assert_eq!(20, cpu.process_interrupt(&mut ram));
+        assert_eq!(0x0048, cpu.state.PC);
+        assert_eq!(Interrupt::Joypad as u8, ram.read8(IF_REG_ADDR));
+    }
+
+    #[test]
+    fn test_ie_push() {
+        let (mut cpu, mut ram) = new_cpu(&[]);
+        cpu.state.ime = true;
+        cpu.state.SP = 0x0000;
+        cpu.state.PC = 0x0234;
+        ram.write8(IE_REG_ADDR, Interrupt::Timer as u8);
+        ram.write8(IF_REG_ADDR, Interrupt::Timer as u8 | Interrupt::LCDStat as u8);
+
+        // The upper byte of PC lands on IE and turns the timer interrupt into the STAT one
+        assert_eq!(20, cpu.process_interrupt(&mut ram));
+        assert_eq!(0x0048, cpu.state.PC);
+        assert_eq!(0x02, ram.read8(IE_REG_ADDR));
+        assert_eq!(0x34, ram.read8(0xFFFE));
+        assert_eq!(Interrupt::Timer as u8, ram.read8(IF_REG_ADDR));
+
+        // Nothing is left to dispatch once IE is cleared, which jumps to 0x0000
+        let if_reg = Interrupt::Timer as u8 | Interrupt::LCDStat as u8;
+        ram.write8(IF_REG_ADDR, if_reg);
+        cpu.state.ime = true;
+        cpu.state.SP = 0x0000;
+        cpu.state.PC = 0x0034;
+        assert_eq!(20, cpu.process_interrupt(&mut ram));
+        assert_eq!(0x0000, cpu.state.PC);
+        assert!(!cpu.state.ime);
+        assert_eq!(0x00, ram.read8(IE_REG_ADDR));
+        assert_eq!(if_reg, ram.read8(IF_REG_ADDR));
+    }
+
+    #[test]
+    fn test_lock_up() {
+        let (mut cpu, mut ram) = new_cpu(&[INC_A, 0xD3, INC_A]);
+        cpu.state.ime = true;
+        ram.write8(IE_REG_ADDR, Interrupt::Timer as u8);
+
+        run(&mut cpu, &mut ram, 2);
+        assert_eq!(
+            Some(LockUp {
+                pc: 0x0101,
+                opcode: 0xD3
+            }),
+            cpu.lock_up()
+        );
+
+        // Neither instructions nor interrupts run any more
+        ram.write8(IF_REG_ADDR, Interrupt::Timer as u8);
+        for _ in 0..3 {
+            assert_eq!(4, cpu.step(&mut ram));
+        }
+        assert_eq!(0x01, cpu.state.A);
+        assert_eq!(0x0101, cpu.state.PC);
+        assert_eq!(Interrupt::Timer as u8, ram.read8(IF_REG_ADDR));
+
+        cpu.reset();
+        assert_eq!(None, cpu.lock_up());
+    }
+
+    #[test]
+    #[should_panic(expected = "illegal opcode 0xFD at 0x0100")]
+    fn test_panic_on_lock_up() {
+        let (mut cpu, mut ram) = new_cpu(&[0xFD]);
+        cpu.set_panic_on_lock_up(true);
+        cpu.step(&mut ram);
+    }
+}
@@ -1,8 +1,14 @@
 use super::super::bus::Bus;
-use super::super::interrupt;
+use super::super::interrupt::{self, Interrupt};
 use super::io::{Reader16, Reader8, Writer16, Writer8};
 use super::oprand::{Condition, Data16, Immediate8, Register16 as R16, Register8 as R8};
-use super::state::{Flag, State};
+use super::state::{Flag, LockUp, State};
+
+const DIV_REG_ADDR: u16 = 0xFF04;
+const KEY1_REG_ADDR: u16 = 0xFF4D;
+
+// The CPU pauses for 2050 M-cycles while switching the speed
+const SPEED_SWITCH_CYCLES: u16 = 2050 * 4;
 
 pub struct Processor<'a, B: Bus + 'a> {
     state: &'a mut State,
@@ -32,13 +38,28 @@ impl<'a, B: Bus + 'a> Processor<'a, B> {
     }
 
     pub fn halt(&mut self) -> &mut Self {
-        self.state.interrupts_before_halt = interrupt::dump_raw_flags(self.bus);
-        self.state.halted = true;
+        // HALT does not stop the CPU if an interrupt is already pending. Without IME, the CPU then fails to
+        // increment PC after reading the next opcode, so the byte after HALT is read twice
+        if !interrupt::pending(self.bus) {
+            self.state.halted = true;
+        } else if !self.state.ime {
+            self.state.halt_bug = true;
+        }
         self
     }
 
     pub fn stop(&mut self) -> &mut Self {
-        // TODO: Implementation
+        // DIV is reset whenever STOP is executed
+        self.bus.write8(DIV_REG_ADDR, 0);
+
+        // On CGB, STOP switches the speed instead when the switch has been prepared through KEY1
+        let key1 = self.bus.read8(KEY1_REG_ADDR);
+        if self.bus.cgb_mode() && key1 & 0x01 != 0 {
+            self.bus.write8_direct(KEY1_REG_ADDR, (key1 ^ 0x80) & 0x80);
+            self.state.stall_cycles = SPEED_SWITCH_CYCLES;
+        } else {
+            self.state.stopped = true;
+        }
         self
     }
 
@@ -512,8 +533,10 @@ impl<'a, B: Bus + 'a> Processor<'a, B> {
     }
 
     pub fn reti(&mut self) -> &mut Self {
+        // Unlike EI, RETI sets IME immediately
         self.ret(Condition::T);
-        self.ei()
+        self.state.ime = true;
+        self
     }
 
     pub fn rst(&mut self, addr: u16) -> &mut Self {
@@ -526,12 +549,38 @@ impl<'a, B: Bus + 'a> Processor<'a, B> {
     }
 
     pub fn ei(&mut self) -> &mut Self {
-        self.state.interrupting = true;
+        self.state.ime_next = true;
         self
     }
 
     pub fn di(&mut self) -> &mut Self {
-        self.state.interrupted = false;
+        self.state.ime = false;
+        self.state.ime_next = false;
+        self
+    }
+
+    // dispatch pushes PC and jumps to the handler of the pending interrupt with the highest priority. The interrupt
+    // is chosen after the upper byte of PC is pushed, so a push which overwrites IE can cancel the dispatch, in
+    // which case the CPU jumps to 0x0000
+    pub fn dispatch(&mut self) -> &mut Self {
+        self.state.ime = false;
+
+        let pc = self.state.PC;
+        self.dec16(R16::SP);
+        self.bus.write8(self.state.SP, (pc >> 8) as u8);
+        let int = interrupt::receive(self.bus);
+        self.dec16(R16::SP);
+        self.bus.write8(self.state.SP, pc as u8);
+
+        self.state.PC = match int {
+            Interrupt::VBlank => 0x40,
+            Interrupt::LCDStat => 0x48,
+            Interrupt::Timer => 0x50,
+            Interrupt::Serial => 0x58,
+            Interrupt::Joypad => 0x60,
+
+            Interrupt::None => 0x0000,
+        };
         self
     }
 
@@ -559,8 +608,11 @@ impl<'a, B: Bus + 'a> Processor<'a, B> {
         self
     }
 
+    // undefined hangs the CPU, which stops reading instructions until the power is cycled
     pub fn undefined(&mut self, opcode: u8) -> &mut Self {
-        panic!("unsupported or unknown opcode specified: 0x{:02X}", opcode);
+        let pc = self.state.PC;
+        self.state.lock_up = Some(LockUp { pc, opcode });
+        self
     }
 }
 
@@ -1,3 +1,5 @@
+use std::fmt;
+
 #[allow(non_snake_case)]
 #[derive(Debug, Copy, Clone)]
 pub struct State {
@@ -15,11 +17,28 @@ pub struct State {
     pub SP: u16,
     pub PC: u16,
 
-    pub interrupting: bool,
-    pub interrupted: bool,
+    pub ime: bool,      // Interrupt master enable
+    pub ime_next: bool, // EI sets IME after the next instruction
 
     pub halted: bool,
-    pub interrupts_before_halt: u8,
+    pub halt_bug: bool, // PC fails to be incremented after the next opcode is read
+
+    pub stopped: bool,
+    pub lock_up: Option<LockUp>, // Set once an illegal opcode has hung the CPU
+    pub stall_cycles: u16,       // Remaining cycles until the CPU resumes after a speed switch or a VRAM DMA (CGB only)
+}
+
+// LockUp is the illegal opcode which hangs the CPU, and where it has been read
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LockUp {
+    pub pc: u16,
+    pub opcode: u8,
+}
+
+impl fmt::Display for LockUp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "illegal opcode 0x{:02X} at 0x{:04X}", self.opcode, self.pc)
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -45,11 +64,15 @@ impl State {
             PC: 0x0000,
             SP: 0x0000,
 
-            interrupting: false,
-            interrupted: false,
+            ime: false,
+            ime_next: false,
 
             halted: false,
-            interrupts_before_halt: 0x00,
+            halt_bug: false,
+
+            stopped: false,
+            lock_up: None,
+            stall_cycles: 0,
         }
     }
 
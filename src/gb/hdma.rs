@@ -0,0 +1,142 @@
+// Each transfer moves data in blocks of 16 bytes
+pub const BLOCK_SIZE: u16 = 0x10;
+
+// Hdma holds the registers of the VRAM DMA of CGB (HDMA1-5), which copies data into VRAM either at once
+// (general purpose DMA) or a block at a time on every HBlank (HBlank DMA)
+pub struct Hdma {
+    source: u16,
+    dest: u16,
+    blocks: u8, // Number of blocks left to transfer
+    hblank: bool,
+}
+
+impl Hdma {
+    pub fn new() -> Self {
+        Hdma {
+            source: 0x0000,
+            dest: 0x0000,
+            blocks: 0,
+            hblank: false,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, data: u8) {
+        // The lower 4 bits of the addresses are ignored, and the destination always lies in VRAM
+        match addr {
+            0xFF51 => self.source = (self.source & 0x00FF) | (data as u16) << 8,
+            0xFF52 => self.source = (self.source & 0xFF00) | (data & 0xF0) as u16,
+            0xFF53 => self.dest = (self.dest & 0x00FF) | ((data & 0x1F) as u16) << 8,
+            0xFF54 => self.dest = (self.dest & 0xFF00) | (data & 0xF0) as u16,
+            _ => unreachable!(),
+        }
+    }
+
+    // start starts a transfer with the value written to HDMA5
+    pub fn start(&mut self, data: u8) {
+        self.blocks = (data & 0x7F) + 1;
+        self.hblank = data & 0x80 != 0;
+    }
+
+    // cancel stops an HBlank DMA, leaving the number of blocks which have not been transferred
+    pub fn cancel(&mut self) {
+        self.hblank = false;
+    }
+
+    // status returns the value of HDMA5, which tells if a transfer is active and the number of blocks left minus 1
+    pub fn status(&self) -> u8 {
+        let active = if self.hblank_active() { 0x00 } else { 0x80 };
+        active | self.blocks.wrapping_sub(1) & 0x7F
+    }
+
+    pub fn active(&self) -> bool {
+        self.blocks > 0
+    }
+
+    pub fn hblank_active(&self) -> bool {
+        self.hblank && self.active()
+    }
+
+    // next_block returns the source and the destination of the next block, and advances the transfer
+    pub fn next_block(&mut self) -> (u16, u16) {
+        let block = (self.source, 0x8000 | self.dest);
+
+        self.source = self.source.wrapping_add(BLOCK_SIZE);
+        self.dest = (self.dest + BLOCK_SIZE) & 0x1FF0;
+        self.blocks -= 1;
+
+        block
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::bus::Bus;
+    use super::super::mmu::Mmu;
+    use super::super::ppu::{LCDStatus, Mode};
+
+    fn setup(source: u16, dest: u16) -> Mmu {
+        let mut mmu = Mmu::new();
+        mmu.set_cgb_mode(true);
+
+        for i in 0..0x40 {
+            mmu.write8(source + i, i as u8 + 1);
+        }
+        mmu.write8(0xFF51, (source >> 8) as u8);
+        mmu.write8(0xFF52, source as u8);
+        mmu.write8(0xFF53, (dest >> 8) as u8);
+        mmu.write8(0xFF54, dest as u8);
+        mmu
+    }
+
+    fn enter_hblank(mmu: &mut Mmu) {
+        let mut status = LCDStatus::new(mmu.read8(0xFF41));
+        status.set_mode(Mode::VRAMRead);
+        mmu.write8_direct(0xFF41, status.raw());
+        status.set_mode(Mode::HBlank);
+        mmu.write8_direct(0xFF41, status.raw());
+    }
+
+    #[test]
+    fn test_general_purpose_dma() {
+        let mut mmu = setup(0xC000, 0x8800);
+
+        mmu.write8(0xFF55, 0x01);
+        assert_eq!(0xFF, mmu.read8(0xFF55));
+        assert_eq!(32 * 2, mmu.take_dma_cycles());
+        assert_eq!(0, mmu.take_dma_cycles());
+
+        assert_eq!(0x01, mmu.read8(0x8800));
+        assert_eq!(0x20, mmu.read8(0x881F));
+        assert_eq!(0x00, mmu.read8(0x8820));
+    }
+
+    #[test]
+    fn test_hblank_dma() {
+        let mut mmu = setup(0xD000, 0x9000);
+        mmu.write8(0xFF4F, 0x01);
+        mmu.write8(0xFF40, 0x80);
+
+        mmu.write8(0xFF55, 0x82);
+        assert_eq!(0x02, mmu.read8(0xFF55));
+        assert_eq!(0x00, mmu.read8(0x9000));
+
+        enter_hblank(&mut mmu);
+        assert_eq!(0x01, mmu.read8(0xFF55));
+        assert_eq!(0x01, mmu.read8(0x9000));
+        assert_eq!(0x10, mmu.read8(0x900F));
+        assert_eq!(0x00, mmu.read8(0x9010));
+        assert_eq!(32, mmu.take_dma_cycles());
+
+        // The transfer goes into the VRAM bank selected at the time
+        mmu.write8(0xFF4F, 0x00);
+        enter_hblank(&mut mmu);
+        assert_eq!(0x00, mmu.read8(0xFF55));
+        assert_eq!(0x11, mmu.read8(0x9010));
+
+        // Cancelling leaves bit 7 set along with the number of blocks left
+        mmu.write8(0xFF55, 0x00);
+        assert_eq!(0x80, mmu.read8(0xFF55));
+        enter_hblank(&mut mmu);
+        assert_eq!(0x00, mmu.read8(0x9020));
+    }
+}
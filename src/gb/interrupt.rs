@@ -59,8 +59,9 @@ pub fn receive<B: Bus>(bus: &mut B) -> Interrupt {
     }
 }
 
-pub fn dump_raw_flags<B: Bus>(bus: &mut B) -> u8 {
-    bus.read8(IF_REG_ADDR)
+// pending returns true if an enabled interrupt has been requested, whether or not IME is set
+pub fn pending<B: Bus>(bus: &mut B) -> bool {
+    bus.read8(IE_REG_ADDR) & bus.read8(IF_REG_ADDR) & 0x1F != 0
 }
 
 #[cfg(test)]
@@ -0,0 +1,108 @@
+// Button is a key of the Game Boy, which pulls a line of P1 low while pressed
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+impl Button {
+    // mask returns the bit of the button, where the direction keys take the lower nibble
+    fn mask(self) -> u8 {
+        match self {
+            Button::Right | Button::A => 0x01,
+            Button::Left | Button::B => 0x02,
+            Button::Up | Button::Select => 0x04,
+            Button::Down | Button::Start => 0x08,
+        }
+    }
+
+    fn is_direction(self) -> bool {
+        matches!(self, Button::Right | Button::Left | Button::Up | Button::Down)
+    }
+}
+
+// Joypad holds the buttons which are pressed, and reads them out through the lines P1 selects
+pub struct Joypad {
+    directions: u8,
+    actions: u8,
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Joypad {
+            directions: 0x00,
+            actions: 0x00,
+        }
+    }
+
+    // read returns P1 for the lines selected by bit 4 (directions) and 5 (actions), where 0 means pressed
+    pub fn read(&self, select: u8) -> u8 {
+        let mut pressed = 0x00;
+        if select & 0x10 == 0 {
+            pressed |= self.directions;
+        }
+        if select & 0x20 == 0 {
+            pressed |= self.actions;
+        }
+        0xC0 | (select & 0x30) | (!pressed & 0x0F)
+    }
+
+    pub fn set(&mut self, button: Button, pressed: bool) {
+        let buttons = if button.is_direction() {
+            &mut self.directions
+        } else {
+            &mut self.actions
+        };
+
+        if pressed {
+            *buttons |= button.mask();
+        } else {
+            *buttons &= !button.mask();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read() {
+        let mut joypad = Joypad::new();
+        joypad.set(Button::Down, true);
+        joypad.set(Button::A, true);
+
+        assert_eq!(0xFF, joypad.read(0x30));
+        assert_eq!(0xE7, joypad.read(0x20));
+        assert_eq!(0xDE, joypad.read(0x10));
+        assert_eq!(0xC6, joypad.read(0x00));
+
+        joypad.set(Button::Down, false);
+        assert_eq!(0xEF, joypad.read(0x20));
+    }
+
+    #[test]
+    fn test_lines() {
+        let lines = [
+            (Button::Right, 0x20, 0xEE),
+            (Button::Left, 0x20, 0xED),
+            (Button::Up, 0x20, 0xEB),
+            (Button::Down, 0x20, 0xE7),
+            (Button::A, 0x10, 0xDE),
+            (Button::B, 0x10, 0xDD),
+            (Button::Select, 0x10, 0xDB),
+            (Button::Start, 0x10, 0xD7),
+        ];
+        for &(button, select, p1) in lines.iter() {
+            let mut joypad = Joypad::new();
+            joypad.set(button, true);
+            assert_eq!(p1, joypad.read(select), "{:?}", button);
+        }
+    }
+}
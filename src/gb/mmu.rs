@@ -1,17 +1,56 @@
+use super::boot_rom::BootRom;
 use super::bus::Bus;
 use super::cartridge::Cartridge;
+use super::hdma::{self, Hdma};
+use super::interrupt::{self, Interrupt};
+use super::joypad::{Button, Joypad};
+use super::model::Model;
+use super::ppu::{ColorPalette, LCDStatus, Mode};
 use super::ram::Ram;
 
+const P1_REG_ADDR: u16 = 0xFF00;
+const VRAM_BANK_SIZE: usize = 0x2000;
+const WRAM_BANK_SIZE: usize = 0x1000;
+const KEY0_REG_ADDR: u16 = 0xFF4C;
+const KEY1_REG_ADDR: u16 = 0xFF4D;
+
 pub struct Mmu {
     memory: Ram,
     cart: Cartridge,
+    boot_rom: Option<BootRom>, // Laid over the cartridge until the boot ROM unmaps itself
+    joypad: Joypad,
+
+    // CGB only hardware
+    cgb_mode: bool,
+    dmg_compat: bool,
+    vram_bank: u8,
+    vram1: Vec<u8>, // VRAM bank 1 (Bank 0 lives in memory)
+    bg_palette: ColorPalette,
+    obj_palette: ColorPalette,
+    wram_bank: u8,
+    wram: Vec<u8>, // WRAM banks 1-7 (Bank 0 lives in memory)
+    hdma: Hdma,
+    dma_cycles: u16, // Cycles the CPU is stalled for by the transfers so far
 }
 
 impl Mmu {
     pub fn new() -> Self {
         Mmu {
             memory: Ram::new(vec![0x00; 1 << 16]),
-            cart: Cartridge::new(vec![0x00; 1 << 15]),
+            cart: Cartridge::empty(),
+            boot_rom: None,
+            joypad: Joypad::new(),
+
+            cgb_mode: false,
+            dmg_compat: false,
+            vram_bank: 0,
+            vram1: vec![0x00; VRAM_BANK_SIZE],
+            bg_palette: ColorPalette::new(),
+            obj_palette: ColorPalette::new(),
+            wram_bank: 0,
+            wram: vec![0x00; WRAM_BANK_SIZE * 7],
+            hdma: Hdma::new(),
+            dma_cycles: 0,
         }
     }
 
@@ -25,8 +64,58 @@ impl Mmu {
         self.cart = cart;
     }
 
-    pub fn simulate_bootloader(&mut self) {
+    pub fn cartridge(&self) -> &Cartridge {
+        &self.cart
+    }
+
+    pub fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cart
+    }
+
+    // set_button presses or releases the button, which requests the joypad interrupt when a selected line falls
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        let lines = self.read8(P1_REG_ADDR);
+        self.joypad.set(button, pressed);
+        if lines & !self.read8(P1_REG_ADDR) & 0x0F != 0 {
+            interrupt::request(self, Interrupt::Joypad);
+        }
+    }
+
+    // set_cgb_mode switches the hardware between DMG and CGB, which enables VRAM banking and the color palettes
+    pub fn set_cgb_mode(&mut self, enabled: bool) {
+        self.cgb_mode = enabled;
+        self.dmg_compat = false;
+        self.memory.write8(KEY1_REG_ADDR, 0x00);
+        self.vram_bank = 0;
+        self.vram1 = vec![0x00; VRAM_BANK_SIZE];
+        self.bg_palette = ColorPalette::new();
+        self.obj_palette = ColorPalette::new();
+        self.wram_bank = 0;
+        self.wram = vec![0x00; WRAM_BANK_SIZE * 7];
+        self.hdma = Hdma::new();
+        self.dma_cycles = 0;
+    }
+
+    // boot clears the memory as at power on and maps the boot ROM, which runs until it writes to 0xFF50
+    pub fn boot(&mut self, boot_rom: BootRom) {
         self.memory = Ram::new(vec![0x00; 1 << 16]);
+        self.boot_rom = Some(boot_rom);
+    }
+
+    // unmap_boot_rom makes the cartridge visible in place of the boot ROM. The CGB boot ROM sets KEY0 to
+    // run a DMG game in the compatibility mode, keeping the colors it has put in the palettes
+    fn unmap_boot_rom(&mut self) {
+        self.boot_rom = None;
+        if self.cgb_mode && self.memory.read8(KEY0_REG_ADDR) & 0x04 != 0 {
+            self.cgb_mode = false;
+            self.dmg_compat = true;
+        }
+    }
+
+    // simulate_bootloader sets the IO registers to the values the boot ROM of the model leaves
+    pub fn simulate_bootloader(&mut self, model: Model) {
+        self.memory = Ram::new(vec![0x00; 1 << 16]);
+        self.boot_rom = None;
         self.memory.write8(0xFF05, 0x00);
         self.memory.write8(0xFF06, 0x00);
         self.memory.write8(0xFF07, 0x00);
@@ -59,9 +148,128 @@ impl Mmu {
         self.memory.write8(0xFF4B, 0x00);
         self.memory.write8(0xFFFF, 0x00);
 
+        // The boot ROM leaves the VBlank interrupt requested, with the unused bits read as 1s
+        self.memory.write8(0xFF0F, 0xE1);
+        match model {
+            Model::Cgb | Model::Agb => {
+                self.memory.write8(0xFF02, 0x7F);
+                self.memory.write8(0xFF46, 0x00);
+            }
+            Model::Sgb | Model::Sgb2 => {
+                self.memory.write8(0xFF02, 0x7E);
+                self.memory.write8(0xFF26, 0xF0);
+                self.memory.write8(0xFF46, 0xFF);
+            }
+            Model::Dmg0 | Model::Dmg | Model::Mgb => {
+                self.memory.write8(0xFF02, 0x7E);
+                self.memory.write8(0xFF46, 0xFF);
+            }
+        }
+
         self.memory.write8(0xFF50, 0x01);
     }
 
+    // set_dmg_compat runs a DMG game on CGB in the compatibility mode, colored in the grayscale which
+    // the boot ROM leaves in the color palettes for games it does not know
+    pub fn set_dmg_compat(&mut self) {
+        self.set_cgb_mode(false);
+        self.dmg_compat = true;
+
+        let colors: [u16; 4] = [0x7FFF, 0x56B5, 0x294A, 0x0000];
+        self.bg_palette.write_spec(0x80);
+        self.obj_palette.write_spec(0x80);
+        for &color in colors.iter() {
+            self.bg_palette.write_data(color as u8);
+            self.bg_palette.write_data((color >> 8) as u8);
+        }
+        // Both of the object palettes
+        for &color in colors.iter().chain(colors.iter()) {
+            self.obj_palette.write_data(color as u8);
+            self.obj_palette.write_data((color >> 8) as u8);
+        }
+    }
+
+    // double_speed returns true while the CPU runs in the double speed mode of CGB
+    pub fn double_speed(&self) -> bool {
+        self.cgb_mode && self.memory.read8(KEY1_REG_ADDR) & 0x80 != 0
+    }
+
+    // take_dma_cycles returns the number of cycles which the CPU has to be stalled for because of the VRAM DMA
+    pub fn take_dma_cycles(&mut self) -> u16 {
+        let cycles = self.dma_cycles;
+        self.dma_cycles = 0;
+        cycles
+    }
+
+    // wram_offset returns the offset of the address in the switchable WRAM bank, where bank 0 selects bank 1
+    fn wram_offset(&self, addr: u16) -> usize {
+        let bank = self.wram_bank.max(1) as usize;
+        (bank - 1) * WRAM_BANK_SIZE + (addr as usize - 0xD000)
+    }
+
+    fn start_hdma(&mut self, data: u8) {
+        // Writing 0 to bit 7 while an HBlank DMA is active cancels it
+        if self.hdma.hblank_active() && data & 0x80 == 0 {
+            self.hdma.cancel();
+            return;
+        }
+
+        self.hdma.start(data);
+
+        // A general purpose DMA copies all the blocks at once
+        if data & 0x80 == 0 {
+            while self.hdma.active() {
+                self.transfer_hdma_block();
+            }
+        }
+    }
+
+    fn transfer_hdma_block(&mut self) {
+        let (source, dest) = self.hdma.next_block();
+        for i in 0..hdma::BLOCK_SIZE {
+            let data = self.read8(source.wrapping_add(i));
+            self.write8(dest + i, data);
+        }
+
+        // The CPU stops for 8 M-cycles per block, which take twice as many cycles in double speed mode
+        self.dma_cycles += if self.double_speed() { 64 } else { 32 };
+    }
+
+    // write_stat_direct updates STAT for the PPU, and moves a block of an HBlank DMA when HBlank starts
+    fn write_stat_direct(&mut self, data: u8) {
+        let mode = LCDStatus::new(self.memory.read8(0xFF41)).mode();
+        self.memory.write8(0xFF41, data);
+
+        let hblank_started = mode != Mode::HBlank && LCDStatus::new(data).mode() == Mode::HBlank;
+        let lcd_enabled = self.memory.read8(0xFF40) & 0x80 != 0;
+        if hblank_started && lcd_enabled && self.memory.read8(0xFF44) < 144 && self.hdma.hblank_active() {
+            self.transfer_hdma_block();
+        }
+    }
+
+    fn write_stat(&mut self, data: u8) {
+        let status = LCDStatus::new(self.memory.read8(0xFF41));
+        // The mode and the coincidence flag are read-only and the unused bit always reads as 1
+        let new_status = LCDStatus::new(0x80 | (data & 0x78) | (status.raw() & 0x07));
+        self.memory.write8(0xFF41, new_status.raw());
+
+        if self.memory.read8(0xFF40) & 0x80 == 0 || status.interrupt_line() {
+            return;
+        }
+
+        // On DMG, writing to STAT enables all the interrupt sources for a moment before the written value takes effect,
+        // which raises the interrupt line during HBlank, VBlank or while LY matches LYC. CGB does not have the bug
+        // even while running a DMG game
+        let quirk_line = match status.mode() {
+            _ if self.cgb_mode || self.dmg_compat => false,
+            Mode::HBlank | Mode::VBlank => true,
+            _ => status.lyc_coincidence(),
+        };
+        if quirk_line || new_status.interrupt_line() {
+            interrupt::request(self, Interrupt::LCDStat);
+        }
+    }
+
     fn dma_transfer(&mut self, value: u8) {
         let start_addr = (value as u16) * 0x100;
         for i in 0..0xA0 {
@@ -73,11 +281,31 @@ impl Mmu {
 impl Bus for Mmu {
     fn read8(&self, addr: u16) -> u8 {
         match addr {
-            0x0000...0x7FFF => self.cart.read(addr),
+            0x0000...0x7FFF => match self.boot_rom.as_ref().and_then(|boot_rom| boot_rom.read(addr)) {
+                Some(data) => data,
+                None => self.cart.read(addr),
+            },
+            0x8000...0x9FFF => self.read8_vram(self.vram_bank, addr),
             0xA000...0xBFFF => self.cart.read(addr),
 
+            0xD000...0xDFFF if self.cgb_mode => self.wram[self.wram_offset(addr)],
+
+            // Joypad register
+            0xFF00 => self.joypad.read(self.memory.read8(addr)),
+
             // Mirror of 0xC000...0xDDFF (Typically not used)
-            0xE000...0xFDFF => self.memory.read8(addr - 0x2000),
+            0xE000...0xFDFF => self.read8(addr - 0x2000),
+
+            // CGB registers
+            0xFF4D if self.cgb_mode => 0x7E | self.memory.read8(addr),
+            0xFF4F if self.cgb_mode => 0xFE | self.vram_bank,
+            0xFF51...0xFF54 if self.cgb_mode => 0xFF,
+            0xFF55 if self.cgb_mode => self.hdma.status(),
+            0xFF68 if self.cgb_mode => self.bg_palette.read_spec(),
+            0xFF69 if self.cgb_mode => self.bg_palette.read_data(),
+            0xFF6A if self.cgb_mode => self.obj_palette.read_spec(),
+            0xFF6B if self.cgb_mode => self.obj_palette.read_data(),
+            0xFF70 if self.cgb_mode => 0xF8 | self.wram_bank,
 
             _ => self.memory.read8(addr),
         }
@@ -90,16 +318,52 @@ impl Bus for Mmu {
     fn write8(&mut self, addr: u16, data: u8) {
         match addr {
             0x0000...0x7FFF => self.cart.write(addr, data),
+            0x8000...0x9FFF if self.vram_bank == 1 => self.vram1[addr as usize - 0x8000] = data,
             0xA000...0xBFFF => self.cart.write(addr, data),
 
+            0xD000...0xDFFF if self.cgb_mode => {
+                let offset = self.wram_offset(addr);
+                self.wram[offset] = data;
+            }
+
             // Mirror of 0xC000...0xDDFF (Typically not used)
-            0xE000...0xFDFF => self.memory.write8(addr - 0x2000, data),
+            0xE000...0xFDFF => self.write8(addr - 0x2000, data),
+
+            // Only the boot ROM can write KEY0 and unmap itself
+            0xFF4C if self.cgb_mode && self.boot_rom.is_some() => self.memory.write8(addr, data),
+            0xFF4C => (),
+            0xFF50 if self.boot_rom.is_some() => {
+                self.memory.write8(addr, data);
+                if data & 0x01 != 0 {
+                    self.unmap_boot_rom();
+                }
+            }
+            0xFF50 => (),
+
+            // CGB registers
+            0xFF4D if self.cgb_mode => {
+                // Only the bit to prepare a speed switch is writable, which STOP carries out
+                let key1 = self.memory.read8(addr);
+                self.memory.write8(addr, (key1 & 0x80) | (data & 0x01));
+            }
+            0xFF4F if self.cgb_mode => self.vram_bank = data & 0x01,
+            0xFF51...0xFF54 if self.cgb_mode => self.hdma.write(addr, data),
+            0xFF55 if self.cgb_mode => self.start_hdma(data),
+            0xFF68 if self.cgb_mode => self.bg_palette.write_spec(data),
+            0xFF69 if self.cgb_mode => self.bg_palette.write_data(data),
+            0xFF6A if self.cgb_mode => self.obj_palette.write_spec(data),
+            0xFF6B if self.cgb_mode => self.obj_palette.write_data(data),
+            0xFF70 if self.cgb_mode => self.wram_bank = data & 0x07,
 
+            // Joypad register, where only the bits to select the buttons are writable
+            0xFF00 => self.memory.write8(addr, data & 0x30),
             // Divider register
             0xFF04 => {
                 // TODO: Should we reset divider's counter as well...?
                 self.memory.write8(addr, 0)
             }
+            // LCD status register
+            0xFF41 => self.write_stat(data),
             // DMA transfer
             0xFF46 => self.dma_transfer(data),
 
@@ -111,4 +375,81 @@ impl Bus for Mmu {
         self.write8(addr, (data & 0xFF) as u8);
         self.write8(addr.wrapping_add(1), (data >> 8) as u8);
     }
+
+    fn write8_direct(&mut self, addr: u16, data: u8) {
+        match addr {
+            0xFF41 => self.write_stat_direct(data),
+            0xFF00...0xFF7F => self.memory.write8(addr, data),
+            _ => self.write8(addr, data),
+        };
+    }
+
+    fn cgb_mode(&self) -> bool {
+        self.cgb_mode
+    }
+
+    fn dmg_compat(&self) -> bool {
+        self.dmg_compat
+    }
+
+    fn read8_vram(&self, bank: u8, addr: u16) -> u8 {
+        if bank == 1 {
+            self.vram1[addr as usize - 0x8000]
+        } else {
+            self.memory.read8(addr)
+        }
+    }
+
+    fn bg_color(&self, palette: u8, color_num: u8) -> u16 {
+        self.bg_palette.color(palette, color_num)
+    }
+
+    fn obj_color(&self, palette: u8, color_num: u8) -> u16 {
+        self.obj_palette.color(palette, color_num)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_joypad() {
+        let mut mmu = Mmu::new();
+        mmu.write8(P1_REG_ADDR, 0x20);
+
+        // Buttons on the lines not selected do not request the interrupt
+        mmu.set_button(Button::Start, true);
+        assert_eq!(0xEF, mmu.read8(P1_REG_ADDR));
+        assert_eq!(0x00, mmu.read8(0xFF0F));
+
+        mmu.set_button(Button::Left, true);
+        assert_eq!(0xED, mmu.read8(P1_REG_ADDR));
+        assert_eq!(Interrupt::Joypad as u8, mmu.read8(0xFF0F));
+    }
+
+    #[test]
+    fn test_wram_banks() {
+        let mut mmu = Mmu::new();
+        mmu.set_cgb_mode(true);
+
+        for bank in 0..8 {
+            mmu.write8(0xFF70, bank);
+            mmu.write8(0xD000, bank + 0x10);
+        }
+
+        // Bank 0 selects bank 1, and the echo RAM follows the selected bank
+        mmu.write8(0xFF70, 0x00);
+        assert_eq!(0xF8, mmu.read8(0xFF70));
+        assert_eq!(0x11, mmu.read8(0xD000));
+        for bank in 1..8 {
+            mmu.write8(0xFF70, bank);
+            assert_eq!(bank + 0x10, mmu.read8(0xD000));
+            assert_eq!(bank + 0x10, mmu.read8(0xF000));
+        }
+
+        mmu.write8(0xC000, 0xAB);
+        mmu.write8(0xFF70, 0x02);
+        assert_eq!(0xAB, mmu.read8(0xC000));
+    }
 }
@@ -1,4 +1,5 @@
 pub mod cartridge;
+pub mod palette;
 pub mod screen;
 
 // TODO: The followings should be private in the future
@@ -7,16 +8,31 @@ pub mod mmu;
 pub mod ppu;
 pub mod timer;
 
+mod boot_rom;
 mod bus;
+mod hdma;
 mod interrupt;
+mod joypad;
+mod model;
 mod ram;
 
-use self::cartridge::Cartridge;
+pub use self::boot_rom::BootRomError;
+pub use self::cpu::LockUp;
+pub use self::joypad::Button;
+pub use self::model::Model;
+
+use self::boot_rom::BootRom;
+use self::cartridge::{Cartridge, CartridgeHeader, Clock, SaveError, SystemClock};
 use self::cpu::Cpu;
 use self::mmu::Mmu;
+use self::palette::Palette;
 use self::ppu::Ppu;
 use self::screen::Screen;
 use self::timer::Timer;
+use std::rc::Rc;
+
+// Number of clocks the PPU takes to draw a frame (456 clocks * 154 lines), which does not change in double speed mode
+const FRAME_CYCLES: u32 = 70224;
 
 pub struct GameBoy {
     cpu: Cpu,
@@ -24,11 +40,15 @@ pub struct GameBoy {
     mmu: Mmu,
     timer: Timer,
     screen: Screen,
+    clock: Rc<Clock>,
+    boot_rom: Option<BootRom>,
+    model: Option<Model>, // None to pick CGB or DMG by the game
 
     paused: bool,
 }
 
 impl GameBoy {
+    // new returns a Game Boy which runs the games supporting CGB on CGB and the others on DMG
     pub fn new() -> Self {
         GameBoy {
             cpu: Cpu::new(),
@@ -36,17 +56,92 @@ impl GameBoy {
             mmu: Mmu::new(),
             timer: Timer::new(),
             screen: Screen::new(),
+            clock: Rc::new(SystemClock),
+            boot_rom: None,
+            model: None,
 
             paused: true,
         }
     }
 
-    pub fn load(&mut self, cart: Cartridge) {
-        self.cpu.simulate_bootloader();
-        self.ppu = Ppu::new();
-        self.mmu.simulate_bootloader();
-        self.mmu.load_cartridge(cart);
+    // with_model returns a Game Boy which runs every game on the hardware of the model
+    pub fn with_model(model: Model) -> Self {
+        GameBoy {
+            model: Some(model),
+            ..Self::new()
+        }
+    }
+
+    // load boots the ROM, in CGB mode if the game supports it. The running game is kept if the ROM is broken
+    pub fn load(&mut self, rom: Vec<u8>) -> Result<(), cartridge::Error> {
+        let cart = Cartridge::with_clock(rom, self.clock.clone())?;
+
+        self.ppu.reset();
         self.timer = Timer::new();
+        match self.boot_rom.clone() {
+            // The hardware follows the boot ROM, and the CGB one switches itself to DMG games
+            Some(boot_rom) => {
+                let cgb = boot_rom.is_cgb();
+                self.cpu.reset();
+                self.mmu.boot(boot_rom);
+                self.mmu.set_cgb_mode(cgb);
+            }
+            None => {
+                let cgb_game = cart.cgb_supported();
+                let model = match self.model {
+                    Some(model) => model,
+                    None if cgb_game => Model::Cgb,
+                    None => Model::Dmg,
+                };
+
+                self.cpu.simulate_bootloader(model.cpu_registers(&cart));
+                self.mmu.simulate_bootloader(model);
+                if model.is_cgb() && !cgb_game {
+                    self.mmu.set_dmg_compat();
+                } else {
+                    self.mmu.set_cgb_mode(model.is_cgb() && cgb_game);
+                }
+                self.timer.set_div(&mut self.mmu, model.div(cgb_game));
+            }
+        }
+        self.mmu.load_cartridge(cart);
+        Ok(())
+    }
+
+    // header returns the header of the cartridge loaded
+    pub fn header(&self) -> &CartridgeHeader {
+        self.mmu.cartridge().header()
+    }
+
+    // save_dirty returns true if the game has written the battery backed RAM since the last export,
+    // which tells frontends when to persist the save
+    pub fn save_dirty(&self) -> bool {
+        self.mmu.cartridge().save_dirty()
+    }
+
+    // export_save returns the battery backed RAM as .sav bytes, or None if the cartridge has no battery
+    pub fn export_save(&mut self) -> Option<Vec<u8>> {
+        self.mmu.cartridge_mut().export_save()
+    }
+
+    // import_save restores the battery backed RAM from .sav bytes, which should be done right after loading the game
+    pub fn import_save(&mut self, data: &[u8]) -> Result<(), SaveError> {
+        self.mmu.cartridge_mut().import_save(data)
+    }
+
+    // set_boot_rom makes the games loaded afterwards start from the boot ROM (DMG, MGB or CGB) instead of
+    // the state it leaves, or from that state again with None
+    pub fn set_boot_rom(&mut self, boot_rom: Option<Vec<u8>>) -> Result<(), BootRomError> {
+        self.boot_rom = match boot_rom {
+            Some(data) => Some(BootRom::new(data)?),
+            None => None,
+        };
+        Ok(())
+    }
+
+    // set_clock changes the clock which the real time clock of cartridges loaded afterwards runs with
+    pub fn set_clock(&mut self, clock: Rc<Clock>) {
+        self.clock = clock;
     }
 
     pub fn step(&mut self) -> Vec<u8> {
@@ -54,20 +149,79 @@ impl GameBoy {
             return self.screen.dump();
         }
 
-        loop {
+        // Run for a frame at most, so that a frame comes back even while the LCD does not produce any
+        let mut cycles = 0;
+        while cycles < FRAME_CYCLES {
+            let stopped = self.cpu.is_stopped();
             let cycle = self.cpu.step(&mut self.mmu);
-            self.ppu.step(&mut self.mmu, cycle);
+            self.cpu.stall(self.mmu.take_dma_cycles());
+
+            // The whole system stays still until a button is pressed while the CPU is stopped
+            if stopped && self.cpu.is_stopped() {
+                break;
+            }
+
+            // In double speed mode, the CPU and the timer run twice as fast as the PPU
+            let ppu_cycle = if self.mmu.double_speed() { cycle / 2 } else { cycle };
+            self.ppu.step(&mut self.mmu, ppu_cycle);
             self.timer.step(&mut self.mmu, cycle);
 
+            cycles += ppu_cycle as u32;
+
+            // STOP turns the LCD off, which leaves the screen blank until a button is pressed
+            if self.cpu.is_stopped() {
+                self.ppu.stop(&mut self.mmu);
+                self.screen.refresh(&self.ppu.transfer_screen());
+                break;
+            }
             if self.ppu.is_screen_prepared() {
+                self.screen.refresh(&self.ppu.transfer_screen());
                 break;
             }
         }
 
-        self.screen.refresh(&self.ppu.transfer_screen());
         self.screen.dump()
     }
 
+    // set_dot_accurate_ppu switches the PPU to render dot by dot with the pixel FIFO,
+    // which is slower but reproduces raster effects made in the middle of a line
+    pub fn set_dot_accurate_ppu(&mut self, enabled: bool) {
+        self.ppu.set_dot_accurate(enabled);
+    }
+
+    // set_palette changes the colors of the screen, which takes effect from the next line even in the middle of a game
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.ppu.set_palette(palette);
+    }
+
+    // lock_up returns the illegal opcode which has hung the CPU, while the screen keeps being drawn
+    pub fn lock_up(&self) -> Option<LockUp> {
+        self.cpu.lock_up()
+    }
+
+    // set_panic_on_lock_up makes illegal opcodes panic with the address, which helps to debug the emulator
+    pub fn set_panic_on_lock_up(&mut self, enabled: bool) {
+        self.cpu.set_panic_on_lock_up(enabled);
+    }
+
+    pub fn press(&mut self, button: Button) {
+        self.mmu.set_button(button, true);
+    }
+
+    pub fn release(&mut self, button: Button) {
+        self.mmu.set_button(button, false);
+    }
+
+    // rumble returns true while the rumble motor of the cartridge is on, which frontends can turn into vibration
+    pub fn rumble(&self) -> bool {
+        self.mmu.cartridge().rumble()
+    }
+
+    // take_tone returns the tone which the speaker of the cartridge (HuC3) has started to play since the last call
+    pub fn take_tone(&mut self) -> Option<u8> {
+        self.mmu.cartridge_mut().take_tone()
+    }
+
     pub fn pause(&mut self) {
         self.paused = true;
     }
@@ -76,3 +230,311 @@ impl GameBoy {
         self.paused = false;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::bus::Bus;
+    use super::screen::{SCREEN_H, SCREEN_W};
+    use super::*;
+
+    // rom returns a ROM without MBC which runs the given program from the entry point
+    fn rom(program: &[u8], cgb: bool) -> Vec<u8> {
+        let mut data = vec![0x00; 0x8000];
+        data[0x0100..0x0100 + program.len()].copy_from_slice(program);
+        data[0x0143] = if cgb { 0x80 } else { 0x00 };
+        // The header checksum covers 0x0134...0x014C
+        data[0x014D] = data[0x0134..0x014D]
+            .iter()
+            .fold(0u8, |sum, &b| sum.wrapping_sub(b).wrapping_sub(1));
+        data
+    }
+
+    // run_frames runs the game boy until the PPU produces the given number of frames
+    fn run_frames(gameboy: &mut GameBoy, n: usize) {
+        for _ in 0..n {
+            gameboy.step();
+            assert!(!gameboy.cpu.is_stopped());
+        }
+    }
+
+    #[test]
+    fn test_step_returns_while_lcd_disabled() {
+        let mut gameboy = GameBoy::new();
+        gameboy
+            .load(rom(
+                &[
+                    0x3E, 0xFF, // LD A,0xFF
+                    0xE0, 0x47, // LDH (0x47),A
+                    0xF0, 0x44, // LDH A,(0x44)
+                    0xFE, 0x48, // CP 0x48
+                    0x20, 0xFA, // JR NZ,-6
+                    0xAF, // XOR A
+                    0xE0, 0x40, // LDH (0x40),A
+                    0x18, 0xFE, // JR -2
+                ],
+                false,
+            ))
+            .unwrap();
+        gameboy.unpause();
+
+        // The frame comes back as soon as the LCD goes off in the middle of the frame, blank in the lightest shade
+        for _ in 0..2 {
+            let screen = gameboy.step();
+            assert_eq!(4 * SCREEN_W as usize * SCREEN_H as usize, screen.len());
+            assert_eq!(0, gameboy.mmu.read8(0xFF44));
+            assert_eq!(0, gameboy.mmu.read8(0xFF41) & 0x03);
+            for pixel in screen.chunks(4) {
+                assert_eq!(&[0x9B, 0xBC, 0x0F, 0xFF], pixel);
+            }
+        }
+    }
+
+    #[test]
+    fn test_stop_waits_for_joypad() {
+        let mut gameboy = GameBoy::new();
+        gameboy
+            .load(rom(
+                &[
+                    0x3E, 0xFF, // LD A,0xFF
+                    0xE0, 0x47, // LDH (0x47),A
+                    0x3E, 0x10, // LD A,0x10
+                    0xE0, 0x00, // LDH (0x00),A
+                    0x10, 0x00, // STOP
+                    0x3E, 0x42, // LD A,0x42
+                    0xE0, 0x80, // LDH (0x80),A
+                    0x18, 0xFE, // JR -2
+                ],
+                false,
+            ))
+            .unwrap();
+        gameboy.unpause();
+
+        // The joypad interrupt requested before STOP does not wake the CPU up
+        interrupt::request(&mut gameboy.mmu, interrupt::Interrupt::Joypad);
+        for _ in 0..3 {
+            gameboy.step();
+        }
+        assert!(gameboy.cpu.is_stopped());
+
+        // Neither does a button on the lines not selected, while the LCD is off with the screen blank
+        gameboy.press(Button::Up);
+        for _ in 0..3 {
+            let screen = gameboy.step();
+            assert!(gameboy.cpu.is_stopped());
+            assert_eq!(0, gameboy.mmu.read8(0xFF44));
+            assert_eq!(0, gameboy.mmu.read8(0xFF41) & 0x03);
+            for pixel in screen.chunks(4) {
+                assert_eq!(&[0x9B, 0xBC, 0x0F, 0xFF], pixel);
+            }
+        }
+        assert_eq!(0x00, gameboy.mmu.read8(0xFF80));
+
+        gameboy.press(Button::Start);
+        run_frames(&mut gameboy, 1);
+        assert!(!gameboy.cpu.is_stopped());
+        assert_eq!(0x42, gameboy.mmu.read8(0xFF80));
+    }
+
+    #[test]
+    fn test_lock_up() {
+        let mut gameboy = GameBoy::new();
+        gameboy.load(rom(&[0x00, 0x00, 0xDB], false)).unwrap();
+        gameboy.unpause();
+
+        run_frames(&mut gameboy, 2);
+        assert_eq!(
+            Some(LockUp {
+                pc: 0x0102,
+                opcode: 0xDB
+            }),
+            gameboy.lock_up()
+        );
+
+        // The PPU keeps drawing frames with the CPU hung
+        gameboy.mmu.write8(0xFF0F, 0x00);
+        run_frames(&mut gameboy, 1);
+        assert_ne!(0x00, gameboy.mmu.read8(0xFF0F) & interrupt::Interrupt::VBlank as u8);
+    }
+
+    #[test]
+    fn test_load_broken_rom() {
+        let mut gameboy = GameBoy::new();
+        gameboy.load(rom(&[0x18, 0xFE], true)).unwrap(); // JR -2
+
+        let mut data = rom(&[], false);
+        data[0x014D] ^= 0xFF;
+        assert!(gameboy.load(data).is_err());
+        assert!(gameboy.load(vec![]).is_err());
+
+        // The game running stays as it is
+        assert!(gameboy.mmu.cartridge().cgb_supported());
+        assert_eq!(0x18, gameboy.mmu.read8(0x0100));
+    }
+
+    #[test]
+    fn test_boot_rom() {
+        let mut boot_rom = vec![0x00; 0x100];
+        boot_rom[..9].copy_from_slice(&[
+            0xFA, 0x00, 0x00, // LD A,(0x0000)
+            0xE0, 0x80, // LDH (0x80),A
+            0x3E, 0x01, // LD A,0x01
+            0xE0, 0x50, // LDH (0x50),A
+        ]);
+
+        let mut gameboy = GameBoy::new();
+        gameboy.set_boot_rom(Some(boot_rom)).unwrap();
+        gameboy
+            .load(rom(
+                &[
+                    0x3E, 0x99, // LD A,0x99
+                    0xE0, 0x81, // LDH (0x81),A
+                    0x18, 0xFE, // JR -2
+                ],
+                true,
+            ))
+            .unwrap();
+        assert_eq!(0xFA, gameboy.mmu.read8(0x0000));
+        gameboy.unpause();
+        run_frames(&mut gameboy, 2);
+
+        // The game runs after the boot ROM unmaps itself, on DMG even though the game supports CGB
+        assert_eq!(0xFA, gameboy.mmu.read8(0xFF80));
+        assert_eq!(0x99, gameboy.mmu.read8(0xFF81));
+        assert_eq!(0x00, gameboy.mmu.read8(0x0000));
+        assert!(!gameboy.mmu.cgb_mode());
+
+        assert_eq!(
+            Err(BootRomError::InvalidSize(0x10)),
+            gameboy.set_boot_rom(Some(vec![0x00; 0x10]))
+        );
+    }
+
+    #[test]
+    fn test_cgb_boot_rom_dmg_compat() {
+        let mut boot_rom = vec![0x00; 0x900];
+        boot_rom[..20].copy_from_slice(&[
+            0x3E, 0x04, // LD A,0x04
+            0xE0, 0x4C, // LDH (0x4C),A
+            0x3E, 0x80, // LD A,0x80
+            0xE0, 0x68, // LDH (0x68),A
+            0x3E, 0x1F, // LD A,0x1F
+            0xE0, 0x69, // LDH (0x69),A
+            0x3E, 0x00, // LD A,0x00
+            0xE0, 0x69, // LDH (0x69),A
+            0x3E, 0x01, // LD A,0x01
+            0xE0, 0x50, // LDH (0x50),A
+        ]);
+
+        let mut gameboy = GameBoy::new();
+        gameboy.set_boot_rom(Some(boot_rom)).unwrap();
+        gameboy.load(rom(&[0x18, 0xFE], false)).unwrap(); // JR -2
+        assert!(gameboy.mmu.cgb_mode());
+        gameboy.unpause();
+        run_frames(&mut gameboy, 2);
+
+        // The DMG game is colored with the palette the boot ROM has set
+        assert!(!gameboy.mmu.cgb_mode());
+        assert!(gameboy.mmu.dmg_compat());
+        assert_eq!(0x001F, gameboy.mmu.bg_color(0, 0));
+
+        // KEY0 cannot be written any more
+        gameboy.mmu.write8(0xFF4C, 0x00);
+        assert_eq!(0x04, gameboy.mmu.read8(0xFF4C));
+    }
+
+    // boot_state returns A and B which the game sees at the entry point on the model
+    fn boot_state(model: Model, cgb: bool) -> (u8, u8) {
+        let mut gameboy = GameBoy::with_model(model);
+        gameboy
+            .load(rom(
+                &[
+                    0xE0, 0x80, // LDH (0x80),A
+                    0x78, // LD A,B
+                    0xE0, 0x81, // LDH (0x81),A
+                    0x18, 0xFE, // JR -2
+                ],
+                cgb,
+            ))
+            .unwrap();
+        assert_eq!((model.div(cgb) >> 8) as u8, gameboy.mmu.read8(0xFF04));
+
+        gameboy.unpause();
+        run_frames(&mut gameboy, 1);
+        (gameboy.mmu.read8(0xFF80), gameboy.mmu.read8(0xFF81))
+    }
+
+    #[test]
+    fn test_models() {
+        let cases = [
+            (Model::Dmg0, false, 0x01, 0xFF),
+            (Model::Dmg, false, 0x01, 0x00),
+            (Model::Mgb, false, 0xFF, 0x00),
+            (Model::Sgb, false, 0x01, 0x00),
+            (Model::Sgb2, false, 0xFF, 0x00),
+            (Model::Cgb, true, 0x11, 0x00),
+            (Model::Agb, true, 0x11, 0x01),
+        ];
+        for &(model, cgb, a, b) in cases.iter() {
+            assert_eq!((a, b), boot_state(model, cgb), "{:?}", model);
+        }
+    }
+
+    #[test]
+    fn test_model_against_game() {
+        // CGB runs DMG games in the compatibility mode
+        let mut gameboy = GameBoy::with_model(Model::Cgb);
+        gameboy.load(rom(&[0x18, 0xFE], false)).unwrap(); // JR -2
+        assert!(!gameboy.mmu.cgb_mode());
+        assert!(gameboy.mmu.dmg_compat());
+        assert_eq!(0x7FFF, gameboy.mmu.bg_color(0, 0));
+        assert_eq!(0x0000, gameboy.mmu.obj_color(1, 3));
+
+        // DMG cannot run CGB games in CGB mode
+        assert_eq!((0x01, 0x00), boot_state(Model::Dmg, true));
+        let mut gameboy = GameBoy::with_model(Model::Dmg);
+        gameboy.load(rom(&[0x18, 0xFE], true)).unwrap(); // JR -2
+        assert!(!gameboy.mmu.cgb_mode());
+        assert!(!gameboy.mmu.dmg_compat());
+    }
+
+    // div_per_frame returns how much DIV advances while the PPU draws a frame
+    fn div_per_frame(gameboy: &mut GameBoy) -> u8 {
+        run_frames(gameboy, 1);
+        let div = gameboy.mmu.read8(0xFF04);
+        run_frames(gameboy, 1);
+        gameboy.mmu.read8(0xFF04).wrapping_sub(div)
+    }
+
+    #[test]
+    fn test_double_speed() {
+        let mut gameboy = GameBoy::new();
+        gameboy.load(rom(&[0x18, 0xFE], true)).unwrap(); // JR -2
+        gameboy.unpause();
+
+        // DIV advances by 70224 / 256 = 274.3 in a frame, which wraps around to 18 as DIV is 8 bits
+        let div = div_per_frame(&mut gameboy);
+        assert!(div == 18 || div == 19, "DIV advanced by {}", div);
+        assert!(!gameboy.mmu.double_speed());
+
+        let mut gameboy = GameBoy::new();
+        gameboy
+            .load(rom(
+                &[
+                    0x3E, 0x01, // LD A,0x01
+                    0xE0, 0x4D, // LDH (0x4D),A
+                    0x10, 0x00, // STOP
+                    0x18, 0xFE, // JR -2
+                ],
+                true,
+            ))
+            .unwrap();
+        gameboy.unpause();
+        run_frames(&mut gameboy, 1);
+        assert!(gameboy.mmu.double_speed());
+        assert_eq!(0xFE, gameboy.mmu.read8(0xFF4D));
+
+        // The timer runs twice as fast while the PPU keeps the same pace
+        let div = div_per_frame(&mut gameboy);
+        assert!(div == 36 || div == 37, "DIV advanced by {}", div);
+    }
+}
@@ -0,0 +1,113 @@
+use super::cartridge::Cartridge;
+
+const TITLE_START: u16 = 0x0134;
+const TITLE_END: u16 = 0x0144;
+
+// Model is the hardware the emulator behaves as, which games tell apart by the state the boot ROM leaves
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Model {
+    Dmg0, // Early DMG with a different boot ROM
+    Dmg,
+    Mgb, // Game Boy Pocket
+    Sgb,
+    Sgb2,
+    Cgb,
+    Agb, // Game Boy Advance running a Game Boy game
+}
+
+// CpuRegisters holds the values which the boot ROM leaves in the registers of the CPU
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CpuRegisters {
+    pub af: u16,
+    pub bc: u16,
+    pub de: u16,
+    pub hl: u16,
+}
+
+impl Model {
+    // is_cgb returns true if the model has the CGB hardware
+    pub fn is_cgb(self) -> bool {
+        self == Model::Cgb || self == Model::Agb
+    }
+
+    // cpu_registers returns the registers after the boot ROM, some of which depend on the header of the game
+    pub fn cpu_registers(self, cart: &Cartridge) -> CpuRegisters {
+        // The flags are left by the header checksum verification on DMG and MGB
+        let flags = if cart.header().header_checksum == 0x00 {
+            0x80
+        } else {
+            0xB0
+        };
+
+        // The CGB boot ROM chooses the colors of DMG games with the checksum of the title, which it computes
+        // only for the games licensed by Nintendo
+        let header = cart.header();
+        let nintendo = header.old_licensee_code == 0x01 || header.new_licensee_code.as_deref() == Some("01");
+        let title_checksum = if nintendo {
+            (TITLE_START..TITLE_END).fold(0u8, |sum, addr| sum.wrapping_add(cart.read(addr)))
+        } else {
+            0x00
+        };
+        let (cgb_bc, cgb_de, cgb_hl) = if cart.cgb_supported() {
+            (0x0000, 0xFF56, 0x000D)
+        } else {
+            ((title_checksum as u16) << 8, 0x0008, 0x007C)
+        };
+
+        let (af, bc, de, hl) = match self {
+            Model::Dmg0 => (0x0100, 0xFF13, 0x00C1, 0x8403),
+            Model::Dmg => (0x0100 | flags, 0x0013, 0x00D8, 0x014D),
+            Model::Mgb => (0xFF00 | flags, 0x0013, 0x00D8, 0x014D),
+            Model::Sgb => (0x0100, 0x0014, 0x0000, 0xC060),
+            Model::Sgb2 => (0xFF00, 0x0014, 0x0000, 0xC060),
+            Model::Cgb => (0x1180, cgb_bc, cgb_de, cgb_hl),
+            // The AGB boot ROM ends with INC B, which games check to detect the AGB
+            Model::Agb => (0x1100, cgb_bc.wrapping_add(0x0100), cgb_de, cgb_hl),
+        };
+        CpuRegisters { af, bc, de, hl }
+    }
+
+    // div returns the internal counter of the timer after the boot ROM, whose upper 8 bits are DIV
+    pub fn div(self, cgb_game: bool) -> u16 {
+        match self {
+            Model::Dmg0 => 0x182C,
+            Model::Dmg | Model::Mgb => 0xABCC,
+            Model::Sgb | Model::Sgb2 => 0xD85C,
+            // The CGB boot ROM takes longer for DMG games as it sets up the colors
+            Model::Cgb | Model::Agb if cgb_game => 0x1EA0,
+            Model::Cgb | Model::Agb => 0x267C,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // cart returns a DMG game titled "TETRIS" with the licensee codes
+    fn cart(old_licensee_code: u8, new_licensee_code: &[u8; 2]) -> Cartridge {
+        let mut data = vec![0x00; 0x8000];
+        data[0x0134..0x013A].copy_from_slice(b"TETRIS");
+        data[0x0144..0x0146].copy_from_slice(new_licensee_code);
+        data[0x014B] = old_licensee_code;
+        data[0x014D] = data[0x0134..0x014D]
+            .iter()
+            .fold(0u8, |sum, &b| sum.wrapping_sub(b).wrapping_sub(1));
+        Cartridge::new(data).unwrap()
+    }
+
+    #[test]
+    fn test_title_checksum() {
+        // 'T' + 'E' + 'T' + 'R' + 'I' + 'S'
+        let checksum = 0xDB;
+
+        assert_eq!(checksum << 8, Model::Cgb.cpu_registers(&cart(0x01, b"00")).bc);
+        assert_eq!(checksum << 8, Model::Cgb.cpu_registers(&cart(0x33, b"01")).bc);
+        assert_eq!((checksum + 1) << 8, Model::Agb.cpu_registers(&cart(0x33, b"01")).bc);
+
+        // Games of other licensees are left with 0
+        assert_eq!(0x0000, Model::Cgb.cpu_registers(&cart(0x08, b"00")).bc);
+        assert_eq!(0x0000, Model::Cgb.cpu_registers(&cart(0x33, b"08")).bc);
+        assert_eq!(0x0100, Model::Agb.cpu_registers(&cart(0x33, b"08")).bc);
+    }
+}
@@ -0,0 +1,99 @@
+// Color is an RGB color which a shade of the DMG LCD is displayed with
+pub type Color = (u8, u8, u8);
+
+// Palette assigns colors to the 4 shades of the DMG LCD, separately for the background (and the window)
+// and the objects using OBP0 and OBP1. The shades are ordered from the lightest to the darkest.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Palette {
+    pub bg: [Color; 4],
+    pub obj0: [Color; 4],
+    pub obj1: [Color; 4],
+}
+
+impl Palette {
+    // new returns a palette which uses the same colors for all the layers
+    pub const fn new(colors: [Color; 4]) -> Self {
+        Palette {
+            bg: colors,
+            obj0: colors,
+            obj1: colors,
+        }
+    }
+}
+
+// The original greenish LCD of DMG
+pub const GREEN: Palette = Palette::new([
+    (0x9B, 0xBC, 0x0F),
+    (0x8B, 0xAC, 0x0F),
+    (0x30, 0x62, 0x30),
+    (0x0F, 0x38, 0x0F),
+]);
+
+#[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+pub const GRAYSCALE: Palette = Palette::new([
+    (0xFF, 0xFF, 0xFF),
+    (0xAA, 0xAA, 0xAA),
+    (0x55, 0x55, 0x55),
+    (0x00, 0x00, 0x00),
+]);
+
+// The LCD of Game Boy Pocket
+#[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+pub const POCKET: Palette = Palette::new([
+    (0xC4, 0xCF, 0xA1),
+    (0x8B, 0x95, 0x6D),
+    (0x4D, 0x53, 0x3C),
+    (0x1F, 0x1F, 0x1F),
+]);
+
+#[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+pub const HIGH_CONTRAST: Palette = Palette::new([
+    (0xFF, 0xFF, 0xFF),
+    (0xFF, 0xD8, 0x00),
+    (0xD0, 0x00, 0x40),
+    (0x00, 0x00, 0x00),
+]);
+
+// Shades of blue, orange and purple, which can be told apart with the common types of color blindness
+#[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+pub const COLOR_BLIND: Palette = Palette {
+    bg: [
+        (0xFF, 0xFF, 0xFF),
+        (0x88, 0xC0, 0xE8),
+        (0x20, 0x60, 0xA8),
+        (0x10, 0x18, 0x30),
+    ],
+    obj0: [
+        (0xFF, 0xFF, 0xFF),
+        (0xF8, 0xC0, 0x70),
+        (0xC8, 0x68, 0x10),
+        (0x30, 0x18, 0x08),
+    ],
+    obj1: [
+        (0xFF, 0xFF, 0xFF),
+        (0xE8, 0xA8, 0xD0),
+        (0xA8, 0x40, 0x88),
+        (0x38, 0x10, 0x30),
+    ],
+};
+
+// PRESETS lists the palettes which can be picked on the web
+#[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+pub const PRESETS: [(&str, Palette); 5] = [
+    ("Green", GREEN),
+    ("Grayscale", GRAYSCALE),
+    ("Pocket", POCKET),
+    ("High Contrast", HIGH_CONTRAST),
+    ("Color Blind", COLOR_BLIND),
+];
+
+// pick_color returns the color of the color number (0-3) of a tile, which is mapped to a shade through
+// the palette register (BGP, OBP0 or OBP1)
+pub fn pick_color(colors: &[Color; 4], palette_reg: u8, color_num: u8) -> Color {
+    colors[shade(palette_reg, color_num) as usize]
+}
+
+// shade returns the shade (0-3) which the palette register maps the color number to
+pub fn shade(palette_reg: u8, color_num: u8) -> u8 {
+    (palette_reg >> (color_num * 2)) & 0b11
+}
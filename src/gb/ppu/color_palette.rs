@@ -0,0 +1,78 @@
+// Each of the 8 palettes has 4 colors of 2 bytes
+const PALETTE_RAM_SIZE: usize = 64;
+
+// ColorPalette is the palette RAM of CGB, which is accessed through a specification register (BCPS/OCPS)
+// holding the index to access and a data register (BCPD/OCPD)
+pub struct ColorPalette {
+    data: [u8; PALETTE_RAM_SIZE],
+    index: u8,
+    auto_increment: bool,
+}
+
+impl ColorPalette {
+    pub fn new() -> Self {
+        ColorPalette {
+            data: [0xFF; PALETTE_RAM_SIZE],
+            index: 0,
+            auto_increment: false,
+        }
+    }
+
+    pub fn read_spec(&self) -> u8 {
+        // Bit 6 is unused and always reads as 1
+        (self.auto_increment as u8) << 7 | 0x40 | self.index
+    }
+
+    pub fn write_spec(&mut self, data: u8) {
+        self.index = data & 0x3F;
+        self.auto_increment = data & 0x80 != 0;
+    }
+
+    pub fn read_data(&self) -> u8 {
+        self.data[self.index as usize]
+    }
+
+    pub fn write_data(&mut self, data: u8) {
+        self.data[self.index as usize] = data;
+
+        // Only writes advance the index, which wraps around within the palette RAM
+        if self.auto_increment {
+            self.index = (self.index + 1) & 0x3F;
+        }
+    }
+
+    // color returns the 15-bit color of the color number (0-3) in the palette (0-7), stored in little endian
+    pub fn color(&self, palette: u8, color_num: u8) -> u16 {
+        let i = (palette as usize & 0x07) * 8 + color_num as usize * 2;
+        (self.data[i] as u16 | (self.data[i + 1] as u16) << 8) & 0x7FFF
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_auto_increment() {
+        let mut palette = ColorPalette::new();
+
+        palette.write_spec(0x80 | 0x3E);
+        assert_eq!(0xFE, palette.read_spec());
+
+        palette.write_data(0x1F);
+        palette.write_data(0x7C);
+        palette.write_data(0xE0);
+        assert_eq!(0xC1, palette.read_spec());
+        assert_eq!(0x7C1F, palette.color(7, 3));
+        assert_eq!(0xE0, palette.data[0]);
+
+        // Reading does not advance the index
+        palette.write_spec(0x00);
+        assert_eq!(0xE0, palette.read_data());
+        assert_eq!(0xE0, palette.read_data());
+        palette.write_data(0x03);
+        palette.write_data(0x04);
+        assert_eq!(0x40, palette.read_spec());
+        assert_eq!(0x04, palette.read_data());
+    }
+}
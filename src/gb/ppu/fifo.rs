@@ -0,0 +1,351 @@
+use super::super::bus::Bus;
+use super::super::palette::{self, Palette};
+use super::super::screen::{FrameBuffer, Pixel, SCREEN_W};
+use super::register::{LCDControl, Register::*};
+use super::renderer::{
+    bg_pixel_color, fetch_obj_row, fetch_tile_id, obj_over_bg, obj_pixel_color, pick_color_num, scan_oam,
+    tile_row_addr, BgPixel, Object, TileAttributes, TileRow, Window,
+};
+use std::collections::VecDeque;
+
+// Each step of the background fetcher takes 2 dots
+const FETCHER_STEP_DOTS: u8 = 2;
+// Fetching an object always stalls the pixel output for at least 6 dots
+const OBJ_FETCH_DOTS: u8 = 6;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum FetcherStep {
+    TileNumber,
+    TileDataLow,
+    TileDataHigh,
+    Push,
+}
+
+struct Fetcher {
+    step: FetcherStep,
+    dots: u8,
+
+    window: bool,
+    map_x: u8, // Number of tiles fetched on the current line (the first tile is SCX / 8 for the background)
+    attrs: TileAttributes,
+    tile_addr: u16,
+    byte1: u8,
+    byte2: u8,
+}
+
+impl Fetcher {
+    fn new(window: bool) -> Self {
+        Fetcher {
+            step: FetcherStep::TileNumber,
+            dots: 0,
+
+            window,
+            map_x: 0,
+            attrs: TileAttributes::default(),
+            tile_addr: 0,
+            byte1: 0,
+            byte2: 0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct ObjPixel {
+    color_num: u8,
+    obj: Object,
+}
+
+// PixelFifo renders a scanline dot by dot with the background/object pixel FIFOs and the fetcher,
+// so that mode 3 has a variable length and writes to the registers in the middle of a line take effect
+pub struct PixelFifo {
+    fetcher: Fetcher,
+    bg_fifo: VecDeque<BgPixel>,
+    obj_fifo: VecDeque<ObjPixel>,
+
+    objs: Vec<Object>,
+    obj_height: u8,
+    stall: u8,
+    penalized_tile: Option<(bool, u8)>,
+
+    x: u8,
+    discard: u8,
+    first_fetch: bool,
+    window_drawn: bool,
+
+    palette: Palette,
+}
+
+impl PixelFifo {
+    pub fn new() -> Self {
+        PixelFifo {
+            fetcher: Fetcher::new(false),
+            bg_fifo: VecDeque::with_capacity(16),
+            obj_fifo: VecDeque::with_capacity(8),
+
+            objs: vec![],
+            obj_height: 8,
+            stall: 0,
+            penalized_tile: None,
+
+            x: 0,
+            discard: 0,
+            first_fetch: true,
+            window_drawn: false,
+
+            palette: palette::GREEN,
+        }
+    }
+
+    // start prepares the FIFOs for a new line at the beginning of mode 3
+    pub fn start<B: Bus>(&mut self, bus: &mut B, window: &mut Window, palette: &Palette) {
+        let control = LCDControl::new(LCDC.read(bus));
+        let y = LY.read(bus);
+
+        if y == WY.read(bus) {
+            window.y_triggered = true;
+        }
+
+        self.fetcher = Fetcher::new(false);
+        self.bg_fifo.clear();
+        self.obj_fifo.clear();
+
+        self.obj_height = control.obj_height();
+        self.objs = scan_oam(bus, y, self.obj_height);
+        self.stall = 0;
+        self.penalized_tile = None;
+
+        self.x = 0;
+        self.discard = SCX.read(bus) % 8;
+        self.first_fetch = true;
+        self.window_drawn = false;
+
+        self.palette = *palette;
+    }
+
+    // tick advances the FIFOs by a dot and returns true once the whole line has been pushed to the LCD
+    pub fn tick<B: Bus>(&mut self, bus: &mut B, frame_buffer: &mut FrameBuffer, window: &mut Window) -> bool {
+        if self.x >= SCREEN_W {
+            return true;
+        }
+
+        if self.stall > 0 {
+            self.stall -= 1;
+            return false;
+        }
+
+        let control = LCDControl::new(LCDC.read(bus));
+
+        if !self.fetcher.window && self.window_reached(bus, control, window) {
+            self.start_window(bus);
+        }
+
+        if control.obj_enabled() && self.discard == 0 && self.fetch_obj(bus) {
+            return false;
+        }
+
+        if let Some(bg) = self.bg_fifo.pop_front() {
+            let obj = self.obj_fifo.pop_front();
+
+            if self.discard > 0 {
+                self.discard -= 1;
+            } else {
+                self.push_pixel(bus, frame_buffer, control, bg, obj);
+            }
+        }
+
+        self.tick_fetcher(bus, control, window);
+
+        if self.x < SCREEN_W {
+            return false;
+        }
+
+        // The internal line counter only advances on lines where the window has actually been drawn
+        if self.window_drawn {
+            window.line += 1;
+        }
+        true
+    }
+
+    fn window_reached<B: Bus>(&mut self, bus: &mut B, control: LCDControl, window: &Window) -> bool {
+        if !control.win_enabled() || !window.y_triggered {
+            return false;
+        }
+
+        let win_x = WX.read(bus);
+        if win_x > SCREEN_W + 6 {
+            return false;
+        }
+
+        // WX holds the window position plus 7, so the window can start even before the first pixel
+        self.x as u16 + 7 == win_x as u16 || (win_x < 7 && self.x == 0)
+    }
+
+    fn start_window<B: Bus>(&mut self, bus: &mut B) {
+        let win_x = WX.read(bus);
+        self.discard = 7u8.saturating_sub(win_x);
+
+        // The pixels of the background which remain in the FIFO are thrown away and the fetcher starts over
+        self.bg_fifo.clear();
+        self.fetcher = Fetcher::new(true);
+        self.first_fetch = false;
+        self.window_drawn = true;
+    }
+
+    // fetch_obj stalls the pixel output to fetch an object which starts at the current position, if there is any
+    fn fetch_obj<B: Bus>(&mut self, bus: &mut B) -> bool {
+        let x = self.x;
+        let pos = self
+            .objs
+            .iter()
+            .position(|obj| obj.x != 0 && obj.x.saturating_sub(8) == x);
+        let obj = match pos {
+            Some(pos) => self.objs.remove(pos),
+            None => return false,
+        };
+
+        let y = LY.read(bus);
+        let (byte1, byte2) = fetch_obj_row(bus, &obj, y, self.obj_height);
+
+        while self.obj_fifo.len() < 8 {
+            self.obj_fifo.push_back(ObjPixel {
+                color_num: 0,
+                obj: Object::default(),
+            });
+        }
+
+        let cgb = bus.cgb_mode();
+
+        // Objects partially hidden beyond the left edge lose their leftmost pixels
+        let hidden = 8 - obj.x.min(8);
+        for tile_x in hidden..8 {
+            let color_bit = if obj.x_flipped() { tile_x } else { 7 - tile_x };
+            let color_num = pick_color_num(color_bit, byte1, byte2);
+
+            // On DMG, an object which has been fetched earlier has priority, so only its transparent pixels are overwritten.
+            // On CGB, the object which appears first in OAM has priority.
+            let pixel = &mut self.obj_fifo[(tile_x - hidden) as usize];
+            if pixel.color_num == 0 || (cgb && color_num != 0 && obj.index < pixel.obj.index) {
+                *pixel = ObjPixel { color_num, obj };
+            }
+        }
+
+        self.stall = OBJ_FETCH_DOTS + self.obj_fetch_wait(bus) - 1;
+        true
+    }
+
+    // obj_fetch_wait returns the number of dots to wait for the background fetcher to finish fetching the tile
+    // under the object. Only the first object on each tile has to wait.
+    fn obj_fetch_wait<B: Bus>(&mut self, bus: &mut B) -> u8 {
+        let (tile, pos) = if self.fetcher.window {
+            let win_pos_x = (self.x + 7).wrapping_sub(WX.read(bus));
+            (win_pos_x / 8, win_pos_x % 8)
+        } else {
+            let bg_x = self.x.wrapping_add(SCX.read(bus));
+            (bg_x / 8, bg_x % 8)
+        };
+
+        let tile = (self.fetcher.window, tile);
+        if self.penalized_tile == Some(tile) {
+            return 0;
+        }
+        self.penalized_tile = Some(tile);
+
+        5u8.saturating_sub(pos)
+    }
+
+    fn push_pixel<B: Bus>(
+        &mut self,
+        bus: &mut B,
+        frame_buffer: &mut FrameBuffer,
+        control: LCDControl,
+        bg: BgPixel,
+        obj: Option<ObjPixel>,
+    ) {
+        let cgb = bus.cgb_mode();
+
+        // On DMG, the background and the window become blank when they are disabled
+        let bg_blank = !control.bgwin_enabled() && !cgb;
+        let bg = if bg_blank { BgPixel::default() } else { bg };
+
+        let color = match obj {
+            Some(p) if control.obj_enabled() && p.color_num != 0 && obj_over_bg(cgb, control, bg, &p.obj) => {
+                obj_pixel_color(bus, &self.palette, &p.obj, p.color_num)
+            }
+            _ if bg_blank => {
+                let (r, g, b) = self.palette.bg[0];
+                Pixel(r, g, b, 255)
+            }
+            _ => bg_pixel_color(bus, &self.palette, bg),
+        };
+
+        frame_buffer.set_pixel(self.x, LY.read(bus), color);
+        self.x += 1;
+    }
+
+    fn tick_fetcher<B: Bus>(&mut self, bus: &mut B, control: LCDControl, window: &Window) {
+        if self.fetcher.step == FetcherStep::Push {
+            self.push_tile_row();
+            return;
+        }
+
+        self.fetcher.dots += 1;
+        if self.fetcher.dots < FETCHER_STEP_DOTS {
+            return;
+        }
+        self.fetcher.dots = 0;
+
+        match self.fetcher.step {
+            FetcherStep::TileNumber => {
+                // SCX and SCY are read on every fetch, so changing them in the middle of a line takes effect
+                let (map_loc, map_x, map_y) = if self.fetcher.window {
+                    (control.win_map_loc(), self.fetcher.map_x, window.line)
+                } else {
+                    let map_x = (SCX.read(bus) / 8).wrapping_add(self.fetcher.map_x);
+                    (control.bg_map_loc(), map_x, LY.read(bus).wrapping_add(SCY.read(bus)))
+                };
+
+                let map_addr = map_loc + (map_y / 8) as u16 * 32 + (map_x % 32) as u16;
+                let (tile_id, attrs) = fetch_tile_id(bus, map_addr);
+
+                self.fetcher.attrs = attrs;
+                self.fetcher.tile_addr = tile_row_addr(bus, tile_id, attrs, map_y);
+                self.fetcher.step = FetcherStep::TileDataLow;
+            }
+            FetcherStep::TileDataLow => {
+                self.fetcher.byte1 = bus.read8_vram(self.fetcher.attrs.bank(), self.fetcher.tile_addr);
+                self.fetcher.step = FetcherStep::TileDataHigh;
+            }
+            FetcherStep::TileDataHigh => {
+                self.fetcher.byte2 = bus.read8_vram(self.fetcher.attrs.bank(), self.fetcher.tile_addr + 1);
+                self.fetcher.step = FetcherStep::Push;
+                self.push_tile_row();
+            }
+            FetcherStep::Push => unreachable!(),
+        }
+    }
+
+    // push_tile_row pushes the fetched row of the tile to the background FIFO once the FIFO becomes empty
+    fn push_tile_row(&mut self) {
+        if !self.bg_fifo.is_empty() {
+            return;
+        }
+
+        self.fetcher.step = FetcherStep::TileNumber;
+
+        // The first fetch on each line is thrown away
+        if self.first_fetch {
+            self.first_fetch = false;
+            return;
+        }
+
+        let tile_row = TileRow {
+            byte1: self.fetcher.byte1,
+            byte2: self.fetcher.byte2,
+            attrs: self.fetcher.attrs,
+        };
+        for tile_x in 0..8 {
+            self.bg_fifo.push_back(tile_row.pixel(tile_x));
+        }
+        self.fetcher.map_x = self.fetcher.map_x.wrapping_add(1);
+    }
+}
@@ -1,11 +1,18 @@
+mod color_palette;
+mod fifo;
 mod register;
 mod renderer;
 
-use self::register::{LCDControl, LCDStatus, Register::*};
-use self::renderer::Renderer;
+pub use self::color_palette::ColorPalette;
+pub use self::register::LCDStatus;
+
+use self::fifo::PixelFifo;
+use self::register::{LCDControl, Register::*};
+use self::renderer::{Renderer, Window};
 use super::bus::Bus;
 use super::interrupt::{self, Interrupt};
-use super::screen::{FrameBuffer, SCREEN_H};
+use super::palette::{self, Palette};
+use super::screen::{FrameBuffer, Pixel, SCREEN_H};
 use std::fmt;
 
 const ONE_CYCLE: u16 = 456;
@@ -23,6 +30,11 @@ pub struct Ppu {
     screen: FrameBuffer,
     screen_buffer: FrameBuffer,
 
+    // When enabled, lines are rendered dot by dot with the pixel FIFO instead of at once
+    dot_accurate: bool,
+    fifo: PixelFifo,
+    palette: Palette,
+
     debug_info: Vec<String>, // For debugging
 }
 
@@ -33,14 +45,59 @@ impl Ppu {
             screen: FrameBuffer::new(),
             screen_buffer: FrameBuffer::new(),
 
+            dot_accurate: false,
+            fifo: PixelFifo::new(),
+            palette: palette::GREEN,
+
             debug_info: vec![],
         }
     }
 
+    // reset brings the PPU back to the initial state, keeping the settings made from outside
+    pub fn reset(&mut self) {
+        *self = Ppu {
+            dot_accurate: self.dot_accurate,
+            palette: self.palette,
+            ..Ppu::new()
+        };
+    }
+
+    pub fn set_dot_accurate(&mut self, enabled: bool) {
+        self.dot_accurate = enabled;
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
     pub fn step<B: Bus>(&mut self, bus: &mut B, cycle: u8) {
         self.update_debug_info(bus);
-        self.update_lcd_status(bus);
 
+        if !LCDControl::new(LCDC.read(bus)).lcd_enabled() {
+            if self.state.lcd_enabled {
+                self.disable_lcd(bus);
+            }
+            return;
+        }
+
+        if !self.state.lcd_enabled {
+            // The LCD starts over from the beginning of a frame, which is not displayed
+            self.state.lcd_enabled = true;
+            self.state.skip_frame = true;
+        }
+
+        if self.dot_accurate {
+            for _ in 0..cycle {
+                self.update_lcd_status(bus);
+                self.tick(bus, 1);
+            }
+        } else {
+            self.update_lcd_status(bus);
+            self.tick(bus, cycle);
+        }
+    }
+
+    fn tick<B: Bus>(&mut self, bus: &mut B, cycle: u8) {
         {
             self.state.clock += cycle as u16;
             if self.state.clock < ONE_CYCLE {
@@ -54,8 +111,14 @@ impl Ppu {
             if next_line == SCREEN_H {
                 interrupt::request(bus, Interrupt::VBlank);
             } else if next_line > SCREEN_H + 9 {
-                self.screen = self.screen_buffer;
+                if self.state.skip_frame {
+                    self.screen = self.blank_screen(bus);
+                    self.state.skip_frame = false;
+                } else {
+                    self.screen = self.screen_buffer;
+                }
                 self.state.screen_prepared = true;
+                self.state.window = Window::new();
 
                 next_line = 0;
             }
@@ -65,6 +128,35 @@ impl Ppu {
         }
     }
 
+    // stop turns the LCD off as STOP does, so that the screen stays blank until the LCD starts over
+    pub fn stop<B: Bus>(&mut self, bus: &mut B) {
+        self.disable_lcd(bus);
+    }
+
+    // disable_lcd stops the LCD, which holds LY at 0 and stays in mode 0 without requesting any interrupts
+    fn disable_lcd<B: Bus>(&mut self, bus: &mut B) {
+        self.state = State::new();
+        self.state.lcd_enabled = false;
+
+        LY.write(bus, 0);
+        let mut status = LCDStatus::new(STAT.read(bus));
+        status.set_mode(Mode::HBlank);
+        STAT.write(bus, status.raw());
+
+        // The screen goes blank while the LCD is off
+        self.screen = self.blank_screen(bus);
+        self.state.screen_prepared = true;
+    }
+
+    fn blank_screen<B: Bus>(&self, bus: &mut B) -> FrameBuffer {
+        if bus.cgb_mode() {
+            return FrameBuffer::filled(Pixel(255, 255, 255, 255));
+        }
+
+        let (r, g, b) = self.palette.bg[0];
+        FrameBuffer::filled(Pixel(r, g, b, 255))
+    }
+
     fn update_debug_info<B: Bus>(&mut self, bus: &mut B) {
         self.debug_info.clear();
 
@@ -97,50 +189,52 @@ impl Ppu {
 
     fn update_lcd_status<B: Bus>(&mut self, bus: &mut B) {
         let mut status = LCDStatus::new(STAT.read(bus));
+        let stat_line = status.interrupt_line();
 
         let cur_line = LY.read(bus);
-        let cur_mode = status.mode();
 
-        let interrupt = if cur_line >= SCREEN_H {
+        if cur_line >= SCREEN_H {
             status.set_mode(Mode::VBlank);
-            status.vblank_interrupt_enabled()
         } else {
             match self.state.clock {
-                0...79 => {
-                    status.set_mode(Mode::OAMRead);
-                    status.oam_interrupt_enabled()
+                0...79 => status.set_mode(Mode::OAMRead),
+                _ if self.dot_accurate => {
+                    if self.state.line_drawn {
+                        status.set_mode(Mode::HBlank);
+                    } else {
+                        if self.state.clock == 80 {
+                            self.fifo.start(bus, &mut self.state.window, &self.palette);
+                        }
+                        self.state.line_drawn = self.fifo.tick(bus, &mut self.screen_buffer, &mut self.state.window);
+
+                        status.set_mode(Mode::VRAMRead);
+                    }
                 }
                 80...251 => {
                     if !self.state.line_drawn {
-                        let mut renderer = Renderer::new(&mut self.screen_buffer, bus);
+                        let mut renderer =
+                            Renderer::new(&mut self.screen_buffer, &mut self.state.window, &self.palette, bus);
                         renderer.render_scanline();
                         self.state.line_drawn = true;
                     }
 
                     status.set_mode(Mode::VRAMRead);
-                    false
-                }
-                _ => {
-                    status.set_mode(Mode::HBlank);
-                    status.hblank_interrupt_enabled()
                 }
+                _ => status.set_mode(Mode::HBlank),
             }
-        };
-
-        if interrupt && status.mode() != cur_mode {
-            interrupt::request(bus, Interrupt::LCDStat);
         }
 
         if cur_line == LYC.read(bus) {
             status.set_lyc_coincidence(true);
-
-            if status.lyc_coincidence_interrupt_enabled() {
-                interrupt::request(bus, Interrupt::LCDStat);
-            }
         } else {
             status.set_lyc_coincidence(false);
         }
 
+        // All the sources share a single interrupt line, so an interrupt is requested only on its rising edge
+        if status.interrupt_line() && !stat_line {
+            interrupt::request(bus, Interrupt::LCDStat);
+        }
+
         STAT.write(bus, status.raw());
     }
 
@@ -169,6 +263,10 @@ pub struct State {
     clock: u16,
     line_drawn: bool,
     screen_prepared: bool,
+    window: Window,
+
+    lcd_enabled: bool,
+    skip_frame: bool,
 }
 
 impl State {
@@ -177,6 +275,652 @@ impl State {
             clock: 0,
             line_drawn: false,
             screen_prepared: false,
+            window: Window::new(),
+
+            lcd_enabled: true,
+            skip_frame: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::mmu::Mmu;
+    use super::super::screen::SCREEN_W;
+    use super::*;
+
+    // shade converts a pixel into a character so that frames can be compared as text
+    fn shade(pixel: Pixel) -> char {
+        match (pixel.0, pixel.1, pixel.2) {
+            (0x9B, 0xBC, 0x0F) => '.',
+            (0x8B, 0xAC, 0x0F) => '-',
+            (0x30, 0x62, 0x30) => '+',
+            (0x0F, 0x38, 0x0F) => '#',
+            _ => '?',
+        }
+    }
+
+    fn dump(frame: &FrameBuffer) -> Vec<String> {
+        (0..SCREEN_H)
+            .map(|y| (0..SCREEN_W).map(|x| shade(frame.get_pixel(x, y))).collect())
+            .collect()
+    }
+
+    fn row(runs: &[(char, usize)]) -> String {
+        runs.iter().map(|&(c, n)| c.to_string().repeat(n)).collect()
+    }
+
+    // run_frame runs the PPU for a whole frame and calls on_line at the beginning of every line
+    fn run_frame<F: FnMut(u8, &mut Mmu)>(ppu: &mut Ppu, mmu: &mut Mmu, mut on_line: F) -> FrameBuffer {
+        let mut line = LY.read(mmu);
+        on_line(line, mmu);
+
+        while !ppu.is_screen_prepared() {
+            ppu.step(mmu, 4);
+
+            if LY.read(mmu) != line {
+                line = LY.read(mmu);
+                on_line(line, mmu);
+            }
+        }
+
+        ppu.transfer_screen()
+    }
+
+    // setup_window_scene fills the background with color 0 and the window with rows of tiles alternating
+    // between color 3 and color 1, so that the window line which has been drawn can be told from a frame
+    fn setup_window_scene() -> Mmu {
+        let mut mmu = Mmu::new();
+
+        for i in 0..16 {
+            mmu.write8(0x8010 + i, 0xFF);
+            mmu.write8(0x8020 + i, if i % 2 == 0 { 0xFF } else { 0x00 });
+        }
+        for i in 0..0x400 {
+            let tile_row = i / 32;
+            mmu.write8(0x9C00 + i, (tile_row % 2) as u8 + 1);
+        }
+
+        LCDC.write(&mut mmu, 0xF1);
+        BGP.write(&mut mmu, 0xE4);
+        mmu
+    }
+
+    fn window_shade(win_line: usize) -> char {
+        if (win_line / 8) % 2 == 0 {
+            '#'
+        } else {
+            '-'
+        }
+    }
+
+    #[test]
+    fn test_window_mid_frame_wx_change() {
+        // Both of the scanline renderer and the pixel FIFO have to produce the same frames
+        for &dot_accurate in &[false, true] {
+            let mut ppu = Ppu::new();
+            ppu.set_dot_accurate(dot_accurate);
+            let mut mmu = setup_window_scene();
+
+            let frame = run_frame(&mut ppu, &mut mmu, |line, mmu| match line {
+                0 => {
+                    WY.write(mmu, 16);
+                    WX.write(mmu, 87);
+                }
+                40 => WX.write(mmu, 47),
+                60 => WX.write(mmu, 200),
+                80 => WX.write(mmu, 7),
+                _ => (),
+            });
+
+            let mut expected = vec![];
+            for _ in 0..16 {
+                expected.push(row(&[('.', 160)]));
+            }
+            for win_line in 0..24 {
+                expected.push(row(&[('.', 80), (window_shade(win_line), 80)]));
+            }
+            for win_line in 24..44 {
+                expected.push(row(&[('.', 40), (window_shade(win_line), 120)]));
+            }
+            for _ in 60..80 {
+                expected.push(row(&[('.', 160)]));
+            }
+            // The window resumes from the line where it stopped rather than from LY - WY
+            for win_line in 44..108 {
+                expected.push(row(&[(window_shade(win_line), 160)]));
+            }
+
+            assert_eq!(expected, dump(&frame));
+        }
+    }
+
+    #[test]
+    fn test_window_mid_frame_wy_change() {
+        // Both of the scanline renderer and the pixel FIFO have to produce the same frames
+        for &dot_accurate in &[false, true] {
+            let mut ppu = Ppu::new();
+            ppu.set_dot_accurate(dot_accurate);
+            let mut mmu = setup_window_scene();
+
+            let frame = run_frame(&mut ppu, &mut mmu, |line, mmu| match line {
+                0 => {
+                    WY.write(mmu, 100);
+                    WX.write(mmu, 7);
+                }
+                20 => WY.write(mmu, 20),
+                // Once triggered, the window stays visible even if WY moves below the current line
+                50 => WY.write(mmu, 120),
+                _ => (),
+            });
+
+            let mut expected = vec![];
+            for _ in 0..20 {
+                expected.push(row(&[('.', 160)]));
+            }
+            for win_line in 0..124 {
+                expected.push(row(&[(window_shade(win_line), 160)]));
+            }
+
+            assert_eq!(expected, dump(&frame));
+
+            // The window is not triggered when WY is set to a line which has already passed
+            let frame = run_frame(&mut ppu, &mut mmu, |line, mmu| match line {
+                0 => WY.write(mmu, 100),
+                30 => WY.write(mmu, 10),
+                _ => (),
+            });
+
+            let expected = vec![row(&[('.', 160)]); 144];
+            assert_eq!(expected, dump(&frame));
+        }
+    }
+
+    // setup_obj_scene prepares tiles for objects on top of a background filled with color 0:
+    // tile 1 is solid color 3, tile 2 is solid color 1, tile 4 has a single pixel of color 3 at its top left corner
+    fn setup_obj_scene() -> Mmu {
+        let mut mmu = Mmu::new();
+
+        for i in 0..16 {
+            mmu.write8(0x8010 + i, 0xFF);
+            mmu.write8(0x8020 + i, if i % 2 == 0 { 0xFF } else { 0x00 });
+        }
+        mmu.write8(0x8040, 0x80);
+        mmu.write8(0x8041, 0x80);
+
+        LCDC.write(&mut mmu, 0x93);
+        BGP.write(&mut mmu, 0xE4);
+        OBP0.write(&mut mmu, 0xE4);
+        OBP1.write(&mut mmu, 0x54);
+        mmu
+    }
+
+    fn set_obj(mmu: &mut Mmu, index: u16, y: u8, x: u8, tile: u8, attrs: u8) {
+        let addr = 0xFE00 + index * 4;
+        mmu.write8(addr, y);
+        mmu.write8(addr + 1, x);
+        mmu.write8(addr + 2, tile);
+        mmu.write8(addr + 3, attrs);
+    }
+
+    // render_frame renders a frame with both of the scanline renderer and the pixel FIFO, which have to agree
+    fn render_frame(mmu: &mut Mmu) -> Vec<String> {
+        let mut ppu = Ppu::new();
+        let frame = dump(&run_frame(&mut ppu, mmu, |_, _| ()));
+
+        let mut ppu = Ppu::new();
+        ppu.set_dot_accurate(true);
+        assert_eq!(frame, dump(&run_frame(&mut ppu, mmu, |_, _| ())));
+
+        frame
+    }
+
+    #[test]
+    fn test_obj_flip_and_palette() {
+        let mut mmu = setup_obj_scene();
+        set_obj(&mut mmu, 0, 16, 8, 4, 0x00);
+        set_obj(&mut mmu, 1, 16, 16, 4, 0x20); // X flip
+        set_obj(&mut mmu, 2, 16, 24, 4, 0x40); // Y flip
+        set_obj(&mut mmu, 3, 16, 32, 4, 0x10); // OBP1
+
+        let frame = render_frame(&mut mmu);
+        assert_eq!(
+            row(&[('#', 1), ('.', 14), ('#', 1), ('.', 8), ('-', 1), ('.', 135)]),
+            frame[0]
+        );
+        assert_eq!(row(&[('.', 16), ('#', 1), ('.', 143)]), frame[7]);
+    }
+
+    #[test]
+    fn test_obj_tall_mode() {
+        let mut mmu = setup_obj_scene();
+        LCDC.write(&mut mmu, 0x97);
+        for i in 0..16 {
+            mmu.write8(0x8030 + i, 0xFF);
+        }
+        // The lowest bit of the tile number is ignored in 8x16 mode
+        set_obj(&mut mmu, 0, 16, 8, 0x03, 0x00);
+        set_obj(&mut mmu, 1, 16, 16, 0x02, 0x40);
+
+        let frame = render_frame(&mut mmu);
+        for y in 0..8 {
+            assert_eq!(row(&[('-', 8), ('#', 8), ('.', 144)]), frame[y]);
+        }
+        for y in 8..16 {
+            assert_eq!(row(&[('#', 8), ('-', 8), ('.', 144)]), frame[y]);
+        }
+        assert_eq!(row(&[('.', 160)]), frame[16]);
+    }
+
+    #[test]
+    fn test_obj_limit_per_line() {
+        let mut mmu = setup_obj_scene();
+        for i in 0..11 {
+            set_obj(&mut mmu, i, 16, 8 + (i as u8) * 8, 1, 0x00);
+        }
+        // Objects off the screen horizontally still count towards the limit
+        set_obj(&mut mmu, 0, 16, 0, 1, 0x00);
+
+        let frame = render_frame(&mut mmu);
+        assert_eq!(row(&[('.', 8), ('#', 72), ('.', 80)]), frame[0]);
+    }
+
+    #[test]
+    fn test_obj_priority() {
+        let mut mmu = setup_obj_scene();
+        // The object with the smaller X coordinate is drawn on top regardless of the OAM order
+        set_obj(&mut mmu, 0, 16, 20, 1, 0x00);
+        set_obj(&mut mmu, 1, 16, 16, 1, 0x10);
+        // With the same X coordinate, the object which appears first in OAM is drawn on top
+        set_obj(&mut mmu, 2, 32, 40, 1, 0x10);
+        set_obj(&mut mmu, 3, 32, 40, 1, 0x00);
+
+        let frame = render_frame(&mut mmu);
+        assert_eq!(row(&[('.', 8), ('-', 8), ('#', 4), ('.', 140)]), frame[0]);
+        assert_eq!(row(&[('.', 32), ('-', 8), ('.', 120)]), frame[16]);
+    }
+
+    #[test]
+    fn test_obj_behind_bg() {
+        let mut mmu = setup_obj_scene();
+        // Fill the left half of the first tile row of the background with color 1
+        for i in 0..10 {
+            mmu.write8(0x9800 + i, 2);
+        }
+        set_obj(&mut mmu, 0, 16, 84, 1, 0x80);
+        // The object above is on top of this one, so this one is not visible even where the background hides the above
+        set_obj(&mut mmu, 1, 16, 85, 1, 0x10);
+
+        let frame = render_frame(&mut mmu);
+        assert_eq!(row(&[('-', 80), ('#', 4), ('-', 1), ('.', 75)]), frame[0]);
+    }
+
+    #[test]
+    fn test_bg_scroll_x() {
+        let mut mmu = setup_obj_scene();
+        // Put a tile of color 3 at the first column and a tile of color 1 at the last column of the background map
+        mmu.write8(0x9800, 1);
+        mmu.write8(0x981F, 2);
+
+        let frame = render_frame(&mut mmu);
+        assert_eq!(row(&[('#', 8), ('.', 152)]), frame[0]);
+
+        SCX.write(&mut mmu, 3);
+        let frame = render_frame(&mut mmu);
+        assert_eq!(row(&[('#', 5), ('.', 155)]), frame[0]);
+
+        // The background wraps around to the first column after the last one
+        SCX.write(&mut mmu, 250);
+        let frame = render_frame(&mut mmu);
+        assert_eq!(row(&[('-', 6), ('#', 8), ('.', 146)]), frame[0]);
+    }
+
+    // mode3_length runs the PPU dot by dot through the first line and returns the number of dots spent in mode 3
+    fn mode3_length(mmu: &mut Mmu) -> u16 {
+        let mut ppu = Ppu::new();
+        ppu.set_dot_accurate(true);
+        LY.write(mmu, 0);
+
+        let mut dots = 0;
+        while LY.read(mmu) == 0 {
+            ppu.step(mmu, 1);
+            if LCDStatus::new(STAT.read(mmu)).mode() == Mode::VRAMRead {
+                dots += 1;
+            }
+        }
+        dots
+    }
+
+    #[test]
+    fn test_mode3_length() {
+        let mut mmu = setup_obj_scene();
+        assert_eq!(172, mode3_length(&mut mmu));
+
+        // Pixels discarded for the fine scroll stretch mode 3
+        SCX.write(&mut mmu, 3);
+        assert_eq!(175, mode3_length(&mut mmu));
+        SCX.write(&mut mmu, 8);
+        assert_eq!(172, mode3_length(&mut mmu));
+        SCX.write(&mut mmu, 0);
+
+        // The fetcher starts over when the window begins
+        LCDC.write(&mut mmu, 0xB3);
+        WX.write(&mut mmu, 87);
+        assert_eq!(178, mode3_length(&mut mmu));
+        LCDC.write(&mut mmu, 0x93);
+
+        // An object waits for the background fetcher unless it is not the first one on the tile
+        set_obj(&mut mmu, 0, 16, 8, 1, 0x00);
+        assert_eq!(183, mode3_length(&mut mmu));
+        set_obj(&mut mmu, 1, 16, 12, 1, 0x00);
+        assert_eq!(189, mode3_length(&mut mmu));
+        set_obj(&mut mmu, 0, 16, 13, 1, 0x00);
+        set_obj(&mut mmu, 1, 0, 0, 0, 0x00);
+        assert_eq!(178, mode3_length(&mut mmu));
+
+        // Objects do not stall the FIFO while they are disabled
+        LCDC.write(&mut mmu, 0x91);
+        assert_eq!(172, mode3_length(&mut mmu));
+    }
+
+    #[test]
+    fn test_mid_scanline_palette_change() {
+        let mut mmu = setup_obj_scene();
+        for i in 0..32 {
+            mmu.write8(0x9800 + i, 1);
+        }
+
+        let mut ppu = Ppu::new();
+        ppu.set_dot_accurate(true);
+
+        // Mode 3 begins at the 80th dot and the first pixel comes out 12 dots later
+        while ppu.state.clock != 80 + 12 + 100 {
+            ppu.step(&mut mmu, 1);
+        }
+        BGP.write(&mut mmu, 0x00);
+
+        let frame = dump(&run_frame(&mut ppu, &mut mmu, |_, _| ()));
+        assert_eq!(row(&[('#', 100), ('.', 60)]), frame[0]);
+        assert_eq!(row(&[('.', 160)]), frame[1]);
+    }
+
+    // setup_cgb_scene prepares a CGB scene where each color of the palette RAM is unique:
+    // tile 1 is solid color 3 in bank 0 and has a single pixel of color 1 at its top left corner in bank 1,
+    // tile 2 is solid color 1 in bank 0
+    fn setup_cgb_scene() -> Mmu {
+        let mut mmu = Mmu::new();
+        mmu.set_cgb_mode(true);
+
+        for i in 0..16 {
+            mmu.write8(0x8010 + i, 0xFF);
+            mmu.write8(0x8020 + i, if i % 2 == 0 { 0xFF } else { 0x00 });
+        }
+        mmu.write8(0xFF4F, 0x01);
+        mmu.write8(0x8010, 0x80);
+        mmu.write8(0xFF4F, 0x00);
+
+        mmu.write8(0xFF68, 0x80);
+        mmu.write8(0xFF6A, 0x80);
+        for i in 0..32 {
+            let (bg, obj) = (cgb_bg_color(i / 4, i % 4), cgb_obj_color(i / 4, i % 4));
+            mmu.write8(0xFF69, bg as u8);
+            mmu.write8(0xFF69, (bg >> 8) as u8);
+            mmu.write8(0xFF6B, obj as u8);
+            mmu.write8(0xFF6B, (obj >> 8) as u8);
+        }
+
+        LCDC.write(&mut mmu, 0x93);
+        mmu
+    }
+
+    fn cgb_bg_color(palette: u8, color_num: u8) -> u16 {
+        (palette * 4 + color_num) as u16
+    }
+
+    fn cgb_obj_color(palette: u8, color_num: u8) -> u16 {
+        ((palette * 4 + color_num) as u16) << 5
+    }
+
+    fn set_bg_tile(mmu: &mut Mmu, map_x: u16, tile: u8, attrs: u8) {
+        mmu.write8(0x9800 + map_x, tile);
+        mmu.write8(0xFF4F, 0x01);
+        mmu.write8(0x9800 + map_x, attrs);
+        mmu.write8(0xFF4F, 0x00);
+    }
+
+    #[test]
+    fn test_cgb_bg_attributes() {
+        let mut mmu = setup_cgb_scene();
+        set_bg_tile(&mut mmu, 0, 1, 0x02); // Palette 2
+        set_bg_tile(&mut mmu, 1, 1, 0x0B); // Bank 1, palette 3
+        set_bg_tile(&mut mmu, 2, 1, 0x28); // Bank 1, X flip
+        set_bg_tile(&mut mmu, 3, 1, 0x48); // Bank 1, Y flip
+
+        for &dot_accurate in &[false, true] {
+            let mut ppu = Ppu::new();
+            ppu.set_dot_accurate(dot_accurate);
+            let frame = run_frame(&mut ppu, &mut mmu, |_, _| ());
+
+            let color = |x, y| frame.get_pixel(x, y);
+            let expected = |palette, color_num| Pixel::from_rgb555(cgb_bg_color(palette, color_num));
+            assert_eq!(expected(2, 3), color(0, 0));
+            assert_eq!(expected(2, 3), color(7, 7));
+            assert_eq!(expected(3, 1), color(8, 0));
+            assert_eq!(expected(3, 0), color(9, 0));
+            assert_eq!(expected(0, 0), color(16, 0));
+            assert_eq!(expected(0, 1), color(23, 0));
+            assert_eq!(expected(0, 0), color(24, 0));
+            assert_eq!(expected(0, 1), color(24, 7));
+        }
+    }
+
+    #[test]
+    fn test_cgb_obj_priority() {
+        let mut mmu = setup_cgb_scene();
+        set_bg_tile(&mut mmu, 7, 1, 0x80); // Priority over objects
+        set_bg_tile(&mut mmu, 8, 1, 0x00);
+        set_bg_tile(&mut mmu, 9, 0, 0x80); // Priority over objects, but color 0
+
+        // The object which appears first in OAM has priority even though the other one is on the left
+        set_obj(&mut mmu, 0, 16, 52, 2, 0x01);
+        set_obj(&mut mmu, 1, 16, 48, 1, 0x02);
+        set_obj(&mut mmu, 2, 16, 64, 2, 0x03);
+        set_obj(&mut mmu, 3, 16, 80, 2, 0x04);
+
+        for &dot_accurate in &[false, true] {
+            let mut ppu = Ppu::new();
+            ppu.set_dot_accurate(dot_accurate);
+
+            let obj = |palette, color_num| Pixel::from_rgb555(cgb_obj_color(palette, color_num));
+            let bg = |palette, color_num| Pixel::from_rgb555(cgb_bg_color(palette, color_num));
+
+            LCDC.write(&mut mmu, 0x93);
+            let frame = run_frame(&mut ppu, &mut mmu, |_, _| ());
+            assert_eq!(obj(2, 3), frame.get_pixel(40, 0));
+            assert_eq!(obj(1, 1), frame.get_pixel(44, 0));
+            assert_eq!(obj(1, 1), frame.get_pixel(51, 0));
+            assert_eq!(bg(0, 3), frame.get_pixel(56, 0));
+            assert_eq!(obj(4, 1), frame.get_pixel(72, 0));
+
+            // Clearing LCDC bit 0 puts all the objects over the background, which is still displayed
+            LCDC.write(&mut mmu, 0x92);
+            let frame = run_frame(&mut ppu, &mut mmu, |_, _| ());
+            assert_eq!(obj(3, 1), frame.get_pixel(56, 0));
+            assert_eq!(bg(0, 3), frame.get_pixel(64, 0));
+        }
+    }
+
+    #[test]
+    fn test_custom_palette() {
+        let mut mmu = setup_obj_scene();
+        set_obj(&mut mmu, 0, 16, 8, 1, 0x00);
+        set_obj(&mut mmu, 1, 16, 16, 1, 0x10); // OBP1
+
+        let palette = Palette {
+            bg: [(0, 0, 0), (0, 0, 1), (0, 0, 2), (0, 0, 3)],
+            obj0: [(0, 1, 0), (0, 1, 1), (0, 1, 2), (0, 1, 3)],
+            obj1: [(0, 2, 0), (0, 2, 1), (0, 2, 2), (0, 2, 3)],
+        };
+
+        for &dot_accurate in &[false, true] {
+            let mut ppu = Ppu::new();
+            ppu.set_dot_accurate(dot_accurate);
+            ppu.set_palette(palette);
+
+            let frame = run_frame(&mut ppu, &mut mmu, |_, _| ());
+            assert_eq!(Pixel(0, 1, 3, 255), frame.get_pixel(0, 0));
+            assert_eq!(Pixel(0, 2, 1, 255), frame.get_pixel(8, 0));
+            assert_eq!(Pixel(0, 0, 0, 255), frame.get_pixel(16, 0));
+
+            // The palette can be changed in the middle of a game and takes effect from the next frame at the latest
+            ppu.set_palette(palette::GRAYSCALE);
+            let frame = run_frame(&mut ppu, &mut mmu, |_, _| ());
+            assert_eq!(Pixel(0x00, 0x00, 0x00, 255), frame.get_pixel(0, 0));
+            assert_eq!(Pixel(0xFF, 0xFF, 0xFF, 255), frame.get_pixel(16, 0));
+        }
+    }
+
+    #[test]
+    fn test_lcd_disabled() {
+        let mut mmu = setup_obj_scene();
+        for i in 0..32 {
+            mmu.write8(0x9800 + i, 1);
+        }
+
+        let mut ppu = Ppu::new();
+        while LY.read(&mut mmu) != 100 {
+            ppu.step(&mut mmu, 4);
+        }
+
+        LCDC.write(&mut mmu, 0x13);
+        mmu.write8(0xFF0F, 0x00);
+        STAT.write(&mut mmu, 0x78);
+        for _ in 0..(ONE_CYCLE as usize * 200) {
+            ppu.step(&mut mmu, 4);
+
+            assert_eq!(0, LY.read(&mut mmu));
+            assert_eq!(Mode::HBlank, LCDStatus::new(STAT.read(&mut mmu)).mode());
+            assert_eq!(0x00, mmu.read8(0xFF0F));
+        }
+        assert_eq!(vec![row(&[('.', 160)]); 144], dump(&ppu.transfer_screen()));
+
+        // The first frame after the LCD is turned on again comes out blank
+        LCDC.write(&mut mmu, 0x93);
+        let frame = dump(&run_frame(&mut ppu, &mut mmu, |_, _| ()));
+        assert_eq!(vec![row(&[('.', 160)]); 144], frame);
+
+        let frame = dump(&run_frame(&mut ppu, &mut mmu, |_, _| ()));
+        assert_eq!(row(&[('#', 160)]), frame[0]);
+    }
+
+    const IF_REG_ADDR: u16 = 0xFF0F;
+    const FRAME_DOTS: usize = ONE_CYCLE as usize * 154;
+
+    // write_stat writes to STAT as the CPU does and discards the interrupt which the write itself may raise
+    fn write_stat<B: Bus>(bus: &mut B, v: u8) {
+        bus.write8(0xFF41, v);
+        bus.write8(IF_REG_ADDR, 0x00);
+    }
+
+    fn count_stat_interrupts<B: Bus>(ppu: &mut Ppu, bus: &mut B, dots: usize) -> usize {
+        let mut count = 0;
+        for _ in 0..dots / 4 {
+            ppu.step(bus, 4);
+
+            if bus.read8(IF_REG_ADDR) & Interrupt::LCDStat as u8 != 0 {
+                bus.write8(IF_REG_ADDR, 0x00);
+                count += 1;
+            }
+        }
+        count
+    }
+
+    #[test]
+    fn test_stat_interrupt_lyc() {
+        let mut mmu = setup_obj_scene();
+        let mut ppu = Ppu::new();
+        write_stat(&mut mmu, 0x40);
+
+        // LY stays equal to LYC for the whole line, but the interrupt is requested only once
+        assert_eq!(1, count_stat_interrupts(&mut ppu, &mut mmu, ONE_CYCLE as usize));
+        assert_eq!(
+            0,
+            count_stat_interrupts(&mut ppu, &mut mmu, FRAME_DOTS - ONE_CYCLE as usize)
+        );
+
+        LYC.write(&mut mmu, 153);
+        assert_eq!(1, count_stat_interrupts(&mut ppu, &mut mmu, FRAME_DOTS));
+    }
+
+    #[test]
+    fn test_stat_interrupt_modes() {
+        let mut mmu = setup_obj_scene();
+        let mut ppu = Ppu::new();
+
+        write_stat(&mut mmu, 0x08);
+        assert_eq!(144, count_stat_interrupts(&mut ppu, &mut mmu, FRAME_DOTS));
+
+        write_stat(&mut mmu, 0x10);
+        assert_eq!(1, count_stat_interrupts(&mut ppu, &mut mmu, FRAME_DOTS));
+
+        write_stat(&mut mmu, 0x20);
+        assert_eq!(144, count_stat_interrupts(&mut ppu, &mut mmu, FRAME_DOTS));
+    }
+
+    #[test]
+    fn test_stat_interrupt_blocking() {
+        let mut mmu = setup_obj_scene();
+        let mut ppu = Ppu::new();
+
+        // The line stays high from HBlank of line 9 through line 10 because LY matches LYC,
+        // so neither the beginning of line 10 nor its HBlank makes a rising edge
+        LYC.write(&mut mmu, 10);
+        write_stat(&mut mmu, 0x48);
+        assert_eq!(143, count_stat_interrupts(&mut ppu, &mut mmu, FRAME_DOTS));
+    }
+
+    #[test]
+    fn test_stat_write_quirk() {
+        let mut mmu = setup_obj_scene();
+        let mut ppu = Ppu::new();
+        write_stat(&mut mmu, 0x00);
+
+        // Mode 3
+        LYC.write(&mut mmu, 100);
+        while LCDStatus::new(mmu.read8(0xFF41)).mode() != Mode::VRAMRead {
+            ppu.step(&mut mmu, 4);
+        }
+        mmu.write8(0xFF41, 0x00);
+        assert_eq!(0x00, mmu.read8(IF_REG_ADDR));
+
+        // Mode 0
+        while LCDStatus::new(mmu.read8(0xFF41)).mode() != Mode::HBlank {
+            ppu.step(&mut mmu, 4);
+        }
+        mmu.write8(0xFF41, 0x00);
+        assert_eq!(Interrupt::LCDStat as u8, mmu.read8(IF_REG_ADDR));
+
+        // The line is already high, so there is no rising edge
+        write_stat(&mut mmu, 0x08);
+        mmu.write8(0xFF41, 0x08);
+        assert_eq!(0x00, mmu.read8(IF_REG_ADDR));
+
+        // The mode bits cannot be overwritten
+        assert_eq!(0x88, mmu.read8(0xFF41));
+    }
+
+    #[test]
+    fn test_stat_write_quirk_cgb() {
+        let mut mmu = setup_obj_scene();
+        mmu.set_cgb_mode(true);
+        let mut ppu = Ppu::new();
+        write_stat(&mut mmu, 0x00);
+
+        LYC.write(&mut mmu, 100);
+        while LCDStatus::new(mmu.read8(0xFF41)).mode() != Mode::HBlank {
+            ppu.step(&mut mmu, 4);
         }
+        mmu.write8(0xFF41, 0x00);
+        assert_eq!(0x00, mmu.read8(IF_REG_ADDR));
     }
 }
@@ -23,7 +23,7 @@ impl Register {
     }
 
     pub fn write<B: Bus>(&self, bus: &mut B, v: u8) {
-        bus.write8(self.address(), v);
+        bus.write8_direct(self.address(), v);
     }
 
     fn address(&self) -> u16 {
@@ -161,4 +161,20 @@ impl LCDStatus {
     pub fn lyc_coincidence_interrupt_enabled(&self) -> bool {
         self.0 & 0b0100_0000 != 0
     }
+
+    pub fn lyc_coincidence(&self) -> bool {
+        self.0 & 0b0000_0100 != 0
+    }
+
+    // interrupt_line returns the state of the STAT interrupt line, which is the OR of all enabled sources
+    pub fn interrupt_line(&self) -> bool {
+        let mode = match self.mode() {
+            Mode::HBlank => self.hblank_interrupt_enabled(),
+            Mode::VBlank => self.vblank_interrupt_enabled(),
+            Mode::OAMRead => self.oam_interrupt_enabled(),
+            Mode::VRAMRead => false,
+        };
+
+        mode || (self.lyc_coincidence() && self.lyc_coincidence_interrupt_enabled())
+    }
 }
@@ -1,23 +1,53 @@
 use super::super::bus::Bus;
+use super::super::palette::{self, Palette};
 use super::super::screen::{FrameBuffer, Pixel, SCREEN_W};
 use super::register::{LCDControl, Register::*};
 
+const OAM_ADDR: u16 = 0xFE00;
+const OAM_ENTRIES: u16 = 40;
+const OBJS_PER_LINE: usize = 10;
+
 pub struct Renderer<'a, B: Bus + 'a> {
     frame_buffer: &'a mut FrameBuffer,
+    window: &'a mut Window,
+    palette: &'a Palette,
     bus: &'a mut B,
+
+    // Background and window pixels on the current line, before the palette is applied
+    bg_pixels: [BgPixel; SCREEN_W as usize],
 }
 
 impl<'a, B: Bus + 'a> Renderer<'a, B> {
-    pub fn new(frame_buffer: &'a mut FrameBuffer, bus: &'a mut B) -> Self {
-        Renderer { frame_buffer, bus }
+    pub fn new(
+        frame_buffer: &'a mut FrameBuffer,
+        window: &'a mut Window,
+        palette: &'a Palette,
+        bus: &'a mut B,
+    ) -> Self {
+        Renderer {
+            frame_buffer,
+            window,
+            palette,
+            bus,
+
+            bg_pixels: [BgPixel::default(); SCREEN_W as usize],
+        }
     }
 
     pub fn render_scanline(&mut self) {
         let control = LCDControl::new(LCDC.read(self.bus));
 
-        if control.bgwin_enabled() {
+        // The window starts at the first line where LY matches WY and stays triggered until the end of the frame
+        if LY.read(self.bus) == WY.read(self.bus) {
+            self.window.y_triggered = true;
+        }
+
+        // In CGB mode, LCDC bit 0 does not hide the background and the window but takes their priority away
+        if control.bgwin_enabled() || self.bus.cgb_mode() {
             self.render_bg_scanline();
             self.render_win_scanline();
+        } else {
+            self.render_blank_scanline();
         }
 
         if control.obj_enabled() {
@@ -25,59 +55,356 @@ impl<'a, B: Bus + 'a> Renderer<'a, B> {
         }
     }
 
+    fn render_blank_scanline(&mut self) {
+        let y = LY.read(self.bus);
+        for x in 0..SCREEN_W {
+            self.bg_pixels[x as usize] = BgPixel::default();
+
+            let (r, g, b) = self.palette.bg[0];
+            self.frame_buffer.set_pixel(x, y, Pixel(r, g, b, 255));
+        }
+    }
+
     fn render_bg_scanline(&mut self) {
-        let tiles_loc = LCDControl::new(LCDC.read(self.bus)).bgwin_tile_loc();
         let map_loc = LCDControl::new(LCDC.read(self.bus)).bg_map_loc();
         let scroll_y = SCY.read(self.bus);
         let scroll_x = SCX.read(self.bus);
 
         let y = LY.read(self.bus);
-        let y_adjusted = (y as u16 + scroll_y as u16) as u8;
-        let tile_offset = (y_adjusted / 8) as u16 * 32;
+        let y_adjusted = y.wrapping_add(scroll_y);
+
+        // The fetch starts from the tile at SCX / 8 and the first SCX % 8 pixels of it are discarded
+        let mut tile_n = (scroll_x / 8) as u16;
+        let mut tile_x = scroll_x % 8;
+        let mut tile_row = fetch_tile_row(self.bus, map_loc, tile_n, y_adjusted);
+
+        for x in 0..SCREEN_W {
+            let pixel = tile_row.pixel(tile_x);
+
+            self.bg_pixels[x as usize] = pixel;
+            let color = bg_pixel_color(self.bus, self.palette, pixel);
+            self.frame_buffer.set_pixel(x, y, color);
+
+            tile_x += 1;
+            if tile_x == 8 {
+                // The background map is 32 tiles wide and wraps around horizontally
+                tile_n = (tile_n + 1) % 32;
+                tile_x = 0;
+                tile_row = fetch_tile_row(self.bus, map_loc, tile_n, y_adjusted);
+            }
+        }
+    }
+
+    fn render_win_scanline(&mut self) {
+        let control = LCDControl::new(LCDC.read(self.bus));
+        let y = LY.read(self.bus);
+        let win_x = WX.read(self.bus);
+
+        // WX holds the window position plus 7, so values above 166 put the window off the screen
+        if !control.win_enabled() || !self.window.y_triggered || win_x > SCREEN_W + 6 {
+            return;
+        }
+
+        let map_loc = control.win_map_loc();
+        let line = self.window.line;
+
+        for x in 0..SCREEN_W {
+            if (x as u16 + 7) < win_x as u16 {
+                continue;
+            }
+
+            let win_pos_x = (x as u16 + 7 - win_x as u16) as u8;
+            let tile_row = fetch_tile_row(self.bus, map_loc, (win_pos_x / 8) as u16, line);
+            let pixel = tile_row.pixel(win_pos_x % 8);
+
+            self.bg_pixels[x as usize] = pixel;
+            let color = bg_pixel_color(self.bus, self.palette, pixel);
+            self.frame_buffer.set_pixel(x, y, color);
+        }
+
+        // The internal line counter only advances on lines where the window has actually been drawn
+        self.window.line += 1;
+    }
+
+    fn render_obj_scanline(&mut self) {
+        let control = LCDControl::new(LCDC.read(self.bus));
+        let height = control.obj_height();
+        let y = LY.read(self.bus);
+        let cgb = self.bus.cgb_mode();
 
-        for tile_n in 0..32 {
-            let tile_id = if tiles_loc == 0x8800 {
-                (self.bus.read8(map_loc + tile_offset + tile_n) as i16 + 128) as u8
-            } else {
-                self.bus.read8(map_loc + tile_offset + tile_n)
-            };
+        let mut objs = scan_oam(self.bus, y, height);
 
-            let tile_addr = tiles_loc + (tile_id as u16) * 16 + (y_adjusted % 8 * 2) as u16;
+        // On DMG, the object with the smaller X coordinate has priority and the one which appears first in OAM wins ties.
+        // On CGB, only the order in OAM matters.
+        if !cgb {
+            objs.sort_by_key(|obj| (obj.x, obj.index));
+        }
 
-            let byte1 = self.bus.read8(tile_addr);
-            let byte2 = self.bus.read8(tile_addr + 1);
+        let mut drawn = [false; SCREEN_W as usize];
+        for obj in objs {
+            let (byte1, byte2) = fetch_obj_row(self.bus, &obj, y, height);
 
-            let x = (tile_n * 8) as u8;
             for tile_x in 0..8 {
-                if x + tile_x < SCREEN_W {
-                    let color_bit = 7 - tile_x;
-                    let (r, g, b) = self.pick_rgb(color_bit, byte1, byte2);
+                let x = obj.x as i16 - 8 + tile_x as i16;
+                if x < 0 || x >= SCREEN_W as i16 || drawn[x as usize] {
+                    continue;
+                }
 
-                    self.frame_buffer.set_pixel(x + tile_x, y, Pixel(r, g, b, 255));
+                let color_bit = if obj.x_flipped() { tile_x } else { 7 - tile_x };
+                let color_num = pick_color_num(color_bit, byte1, byte2);
+                if color_num == 0 {
+                    // Color 0 is transparent and lets objects with lower priority show through
+                    continue;
                 }
+
+                // Even when the background hides it, this object still masks the ones with lower priority
+                drawn[x as usize] = true;
+                if !obj_over_bg(cgb, control, self.bg_pixels[x as usize], &obj) {
+                    continue;
+                }
+
+                let color = obj_pixel_color(self.bus, self.palette, &obj, color_num);
+                self.frame_buffer.set_pixel(x as u8, y, color);
             }
         }
     }
+}
+
+// scan_oam returns the objects on the given line in OAM order, up to the hardware limit of 10 per line
+pub fn scan_oam<B: Bus>(bus: &mut B, y: u8, height: u8) -> Vec<Object> {
+    let mut objs = Vec::with_capacity(OBJS_PER_LINE);
+
+    for index in 0..OAM_ENTRIES {
+        let addr = OAM_ADDR + index * 4;
+        let obj = Object {
+            index: index as u8,
+            y: bus.read8(addr),
+            x: bus.read8(addr + 1),
+            tile: bus.read8(addr + 2),
+            attrs: bus.read8(addr + 3),
+        };
+
+        // Object's Y position is stored with an offset of 16 so that objects can be partially hidden above the screen
+        let top = obj.y as u16;
+        let line = y as u16 + 16;
+        if top <= line && line < top + height as u16 {
+            objs.push(obj);
+            if objs.len() == OBJS_PER_LINE {
+                break;
+            }
+        }
+    }
+
+    objs
+}
+
+// fetch_tile_row returns the given row of pixels (0-255) of the tile placed at the given column (0-31) of a tile map
+pub fn fetch_tile_row<B: Bus>(bus: &mut B, map_loc: u16, map_x: u16, map_y: u8) -> TileRow {
+    let map_addr = map_loc + (map_y / 8) as u16 * 32 + map_x;
+    let (tile_id, attrs) = fetch_tile_id(bus, map_addr);
+    let tile_addr = tile_row_addr(bus, tile_id, attrs, map_y);
+
+    TileRow {
+        byte1: bus.read8_vram(attrs.bank(), tile_addr),
+        byte2: bus.read8_vram(attrs.bank(), tile_addr + 1),
+        attrs,
+    }
+}
+
+// fetch_tile_id returns the tile number on the tile map and its attributes, which CGB keeps in VRAM bank 1
+pub fn fetch_tile_id<B: Bus>(bus: &mut B, map_addr: u16) -> (u8, TileAttributes) {
+    let attrs = if bus.cgb_mode() {
+        bus.read8_vram(1, map_addr)
+    } else {
+        0x00
+    };
+
+    (bus.read8_vram(0, map_addr), TileAttributes::new(attrs))
+}
+
+// tile_row_addr returns the address of the row of the background or window tile on the given line (0-255)
+pub fn tile_row_addr<B: Bus>(bus: &mut B, tile_id: u8, attrs: TileAttributes, map_y: u8) -> u16 {
+    let tiles_loc = LCDControl::new(LCDC.read(bus)).bgwin_tile_loc();
+    let tile_id = if tiles_loc == 0x8800 {
+        (tile_id as i16 + 128) as u8
+    } else {
+        tile_id
+    };
+
+    let row = if attrs.y_flipped() { 7 - map_y % 8 } else { map_y % 8 };
+    tiles_loc + (tile_id as u16) * 16 + (row * 2) as u16
+}
+
+// fetch_obj_row returns the two bytes which represent the row of pixels of the object on the given line
+pub fn fetch_obj_row<B: Bus>(bus: &mut B, obj: &Object, y: u8, height: u8) -> (u8, u8) {
+    let mut row = y + 16 - obj.y;
+    if obj.y_flipped() {
+        row = height - 1 - row;
+    }
+
+    // Objects always take their tiles from 0x8000 and the lowest bit of the tile number is ignored in 8x16 mode
+    let tile_id = if height == 16 { obj.tile & 0xFE } else { obj.tile };
+    let tile_addr = 0x8000 + (tile_id as u16) * 16 + (row as u16) * 2;
+
+    let bank = if bus.cgb_mode() { obj.bank() } else { 0 };
+    (bus.read8_vram(bank, tile_addr), bus.read8_vram(bank, tile_addr + 1))
+}
+
+pub fn pick_color_num(bit: u8, byte1: u8, byte2: u8) -> u8 {
+    let lo = (byte1 & (1 << bit) != 0) as u8;
+    let hi = (byte2 & (1 << bit) != 0) as u8;
+
+    (hi << 1) | lo
+}
+
+// bg_pixel_color returns the color which a pixel of the background or the window is displayed with
+pub fn bg_pixel_color<B: Bus>(bus: &mut B, palette: &Palette, pixel: BgPixel) -> Pixel {
+    if bus.cgb_mode() {
+        return Pixel::from_rgb555(bus.bg_color(pixel.palette, pixel.color_num));
+    }
+    if bus.dmg_compat() {
+        let shade = palette::shade(BGP.read(bus), pixel.color_num);
+        return Pixel::from_rgb555(bus.bg_color(0, shade));
+    }
+
+    let (r, g, b) = palette::pick_color(&palette.bg, BGP.read(bus), pixel.color_num);
+    Pixel(r, g, b, 255)
+}
+
+// obj_pixel_color returns the color which a pixel of the object is displayed with
+pub fn obj_pixel_color<B: Bus>(bus: &mut B, palette: &Palette, obj: &Object, color_num: u8) -> Pixel {
+    if bus.cgb_mode() {
+        return Pixel::from_rgb555(bus.obj_color(obj.cgb_palette(), color_num));
+    }
+    if bus.dmg_compat() {
+        let (palette, reg) = if obj.palette1() { (1, OBP1) } else { (0, OBP0) };
+        let shade = palette::shade(reg.read(bus), color_num);
+        return Pixel::from_rgb555(bus.obj_color(palette, shade));
+    }
+
+    let (r, g, b) = if obj.palette1() {
+        palette::pick_color(&palette.obj1, OBP1.read(bus), color_num)
+    } else {
+        palette::pick_color(&palette.obj0, OBP0.read(bus), color_num)
+    };
+    Pixel(r, g, b, 255)
+}
+
+// obj_over_bg returns true if an opaque pixel of the object is drawn over the background or window pixel
+pub fn obj_over_bg(cgb: bool, control: LCDControl, bg: BgPixel, obj: &Object) -> bool {
+    // Color 0 of the background is always behind objects, and so is the whole background on CGB when LCDC bit 0 is off
+    if bg.color_num == 0 || (cgb && !control.bgwin_enabled()) {
+        return true;
+    }
+
+    !bg.priority && !obj.behind_bg()
+}
+
+// BgPixel is a pixel of the background or the window before the palette is applied
+#[derive(Debug, Copy, Clone, Default)]
+pub struct BgPixel {
+    pub color_num: u8,
+    pub palette: u8,    // CGB only
+    pub priority: bool, // CGB only
+}
+
+// TileRow is a row of pixels of a background or window tile
+pub struct TileRow {
+    pub byte1: u8,
+    pub byte2: u8,
+    pub attrs: TileAttributes,
+}
+
+impl TileRow {
+    // pixel returns the pixel at the given position (0-7) from the left of the tile
+    pub fn pixel(&self, tile_x: u8) -> BgPixel {
+        let bit = if self.attrs.x_flipped() { tile_x } else { 7 - tile_x };
+
+        BgPixel {
+            color_num: pick_color_num(bit, self.byte1, self.byte2),
+            palette: self.attrs.palette(),
+            priority: self.attrs.bg_priority(),
+        }
+    }
+}
 
-    fn render_win_scanline(&mut self) {}
-    fn render_obj_scanline(&mut self) {}
+// TileAttributes represents the attributes of a tile on the background map (CGB only)
+#[derive(Debug, Copy, Clone, Default)]
+pub struct TileAttributes(u8);
+
+impl TileAttributes {
+    pub fn new(v: u8) -> Self {
+        TileAttributes(v)
+    }
 
-    fn pick_rgb(&mut self, bit: u8, byte1: u8, byte2: u8) -> (u8, u8, u8) {
-        let lo = (byte1 & (1 << bit) != 0) as u8;
-        let hi = (byte2 & (1 << bit) != 0) as u8;
+    pub fn palette(&self) -> u8 {
+        self.0 & 0b0000_0111
+    }
 
-        let color_num = (hi << 1) | lo;
+    pub fn bank(&self) -> u8 {
+        (self.0 >> 3) & 0x01
+    }
 
-        let palette = BGP.read(self.bus);
-        let color = ((palette >> (color_num * 2)) & 0b11) as usize;
+    pub fn x_flipped(&self) -> bool {
+        self.0 & (1 << 5) != 0
+    }
 
-        PALETTE[color]
+    pub fn y_flipped(&self) -> bool {
+        self.0 & (1 << 6) != 0
     }
+
+    pub fn bg_priority(&self) -> bool {
+        self.0 & (1 << 7) != 0
+    }
+}
+
+// Object represents an entry of OAM (Object Attribute Memory)
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Object {
+    pub index: u8,
+    pub y: u8,
+    pub x: u8,
+    pub tile: u8,
+    pub attrs: u8,
 }
 
-const PALETTE: [(u8, u8, u8); 4] = [
-    (0x9B, 0xBC, 0x0F),
-    (0x8B, 0xAC, 0x0F),
-    (0x30, 0x62, 0x30),
-    (0x0F, 0x38, 0x0F),
-];
+impl Object {
+    pub fn behind_bg(&self) -> bool {
+        self.attrs & (1 << 7) != 0
+    }
+
+    pub fn y_flipped(&self) -> bool {
+        self.attrs & (1 << 6) != 0
+    }
+
+    pub fn x_flipped(&self) -> bool {
+        self.attrs & (1 << 5) != 0
+    }
+
+    pub fn palette1(&self) -> bool {
+        self.attrs & (1 << 4) != 0
+    }
+
+    pub fn bank(&self) -> u8 {
+        (self.attrs >> 3) & 0x01
+    }
+
+    pub fn cgb_palette(&self) -> u8 {
+        self.attrs & 0b0000_0111
+    }
+}
+
+// Window holds the internal state of the window layer which persists across scanlines
+pub struct Window {
+    pub y_triggered: bool,
+    pub line: u8,
+}
+
+impl Window {
+    pub fn new() -> Self {
+        Window {
+            y_triggered: false,
+            line: 0,
+        }
+    }
+}
@@ -3,9 +3,23 @@ pub const SCREEN_H: u8 = 144;
 const SCREEN_W_SZ: usize = SCREEN_W as usize;
 const SCREEN_H_SZ: usize = SCREEN_H as usize;
 
-#[derive(Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Pixel(pub u8, pub u8, pub u8, pub u8);
 
+impl Pixel {
+    // from_rgb555 converts a 15-bit color of CGB (5 bits for each of red, green and blue) into a pixel
+    pub fn from_rgb555(color: u16) -> Self {
+        let scale = |c: u16| ((c << 3) | (c >> 2)) as u8;
+
+        Pixel(
+            scale(color & 0x1F),
+            scale((color >> 5) & 0x1F),
+            scale((color >> 10) & 0x1F),
+            255,
+        )
+    }
+}
+
 #[derive(Copy, Clone)]
 pub struct FrameBuffer {
     data: [[Pixel; SCREEN_W_SZ]; SCREEN_H_SZ],
@@ -18,6 +32,12 @@ impl FrameBuffer {
         }
     }
 
+    pub fn filled(pixel: Pixel) -> Self {
+        FrameBuffer {
+            data: [[pixel; SCREEN_W_SZ]; SCREEN_H_SZ],
+        }
+    }
+
     pub fn get_pixel(&self, x: u8, y: u8) -> Pixel {
         self.data[y as usize][x as usize]
     }
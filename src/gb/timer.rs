@@ -20,6 +20,12 @@ impl Timer {
         }
     }
 
+    // set_div sets the internal counter of the divider, whose upper 8 bits are DIV
+    pub fn set_div<B: Bus>(&mut self, bus: &mut B, div: u16) {
+        self.divider.cycles = div & 0xFF;
+        bus.write8_direct(DIV_REG_ADDR, (div >> 8) as u8);
+    }
+
     pub fn step<B: Bus>(&mut self, bus: &mut B, cycle: u8) {
         {
             let overflowed = self.divider.inc(cycle as u16);
@@ -59,8 +65,9 @@ impl Timer {
     }
 
     fn inc_divider_reg<B: Bus>(&mut self, bus: &mut B) {
+        // Writes to DIV from the CPU reset it, so the timer itself has to write directly
         let v = bus.read8(DIV_REG_ADDR).wrapping_add(1);
-        bus.write8(DIV_REG_ADDR, v);
+        bus.write8_direct(DIV_REG_ADDR, v);
     }
 
     fn inc_timer_reg<B: Bus>(&mut self, bus: &mut B) {
@@ -1,15 +1,117 @@
-#[macro_use]
-extern crate stdweb;
-
 mod gb;
 
+use self::gb::GameBoy;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead};
+use std::path::Path;
+use std::process;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// A frame takes 70224 clocks at 4194304 Hz, which is about 59.7 frames a second
+const FRAME_DURATION: Duration = Duration::from_nanos(16_742_706);
+
+// Saves of battery backed cartridges are written to the disk once a second at most
+const SAVE_INTERVAL_FRAMES: u32 = 60;
+
+// The CLI runs a game without a screen at the pace of the real hardware, keeping its battery backed RAM
+// in <rom>.sav next to the ROM. It quits after the given number of frames, or when stdin gets a line or closes.
 fn main() {
-    stdweb::initialize();
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 || args.len() > 3 {
+        eprintln!("usage: {} <rom> [frames]", args[0]);
+        process::exit(2);
+    }
+
+    let frames = match args.get(2).map(|frames| frames.parse::<u32>()) {
+        Some(Ok(frames)) => Some(frames),
+        Some(Err(err)) => exit(&format!("Invalid number of frames: {}", err)),
+        None => None,
+    };
+
+    let rom_path = Path::new(&args[1]);
+    let rom = match fs::read(rom_path) {
+        Ok(rom) => rom,
+        Err(err) => exit(&format!("Failed to read the ROM: {}", err)),
+    };
+
+    let mut gameboy = GameBoy::new();
+    if let Err(err) = gameboy.load(rom) {
+        exit(&format!("Failed to load the ROM: {}", err));
+    }
+
+    let save_path = rom_path.with_extension("sav");
+    restore_save(&mut gameboy, &save_path);
+
+    let quit = wait_for_stdin();
+    let mut frame: u32 = 0;
+    let mut deadline = Instant::now();
+    gameboy.unpause();
+
+    while frames.is_none_or(|frames| frame < frames) && quit.try_recv() == Err(TryRecvError::Empty) {
+        gameboy.step();
+        frame += 1;
+
+        if frame.is_multiple_of(SAVE_INTERVAL_FRAMES) && gameboy.save_dirty() {
+            store_save(&mut gameboy, &save_path);
+        }
 
-    let message = "Hello, World!";
-    js! {
-        console.log( @{message} );
+        // Sleep until the frame would end on the hardware, without catching up on frames which took too long
+        deadline += FRAME_DURATION;
+        let now = Instant::now();
+        if deadline > now {
+            thread::sleep(deadline - now);
+        } else {
+            deadline = now;
+        }
     }
 
-    stdweb::event_loop();
+    if gameboy.save_dirty() {
+        store_save(&mut gameboy, &save_path);
+    }
+}
+
+// exit prints the error and terminates the process
+fn exit(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+// wait_for_stdin returns a receiver which gets a message once a line is entered or stdin is closed
+fn wait_for_stdin() -> Receiver<()> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut line = String::new();
+        let _ = io::stdin().lock().read_line(&mut line);
+        let _ = sender.send(());
+    });
+    receiver
+}
+
+// store_save writes the battery backed RAM to the file
+fn store_save(gameboy: &mut GameBoy, path: &Path) {
+    let save = match gameboy.export_save() {
+        Some(save) => save,
+        None => return,
+    };
+
+    if let Err(err) = fs::write(path, save) {
+        eprintln!("Failed to store the save to {}: {}", path.display(), err);
+    }
+}
+
+// restore_save reads the save of the game back from the file, if there is one. A broken save stops the CLI,
+// since the save written on exit would replace it otherwise
+fn restore_save(gameboy: &mut GameBoy, path: &Path) {
+    let save = match fs::read(path) {
+        Ok(save) => save,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return,
+        Err(err) => exit(&format!("Failed to read the save {}: {}", path.display(), err)),
+    };
+
+    if let Err(err) = gameboy.import_save(&save) {
+        exit(&format!("Failed to restore the save {}: {}", path.display(), err));
+    }
 }
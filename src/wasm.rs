@@ -3,7 +3,8 @@ extern crate stdweb;
 
 mod gb;
 
-use self::gb::cartridge::Cartridge;
+use self::gb::cartridge::Clock;
+use self::gb::palette;
 use self::gb::screen::{SCREEN_H, SCREEN_W};
 use self::gb::GameBoy;
 use std::cell::RefCell;
@@ -12,15 +13,28 @@ use stdweb::traits::*;
 use stdweb::unstable::TryInto;
 use stdweb::web;
 use stdweb::web::event::{ChangeEvent, ProgressLoadEvent};
-use stdweb::web::html_element::{CanvasElement, InputElement};
+use stdweb::web::html_element::{CanvasElement, InputElement, SelectElement};
 use stdweb::web::{document, CanvasRenderingContext2d, FileList, FileReader, FileReaderResult};
 
+// Saves of battery backed cartridges are written to the local storage once a second at most
+const SAVE_INTERVAL_FRAMES: u32 = 60;
+
 macro_rules! enclose {
     ([$($x: ident), *] $y: expr) => {
         {$(let $x = $x.clone();)* $y}
     }
 }
 
+// BrowserClock reads the time from JavaScript, since the clock of the system is not available in wasm
+struct BrowserClock;
+
+impl Clock for BrowserClock {
+    fn now(&self) -> u64 {
+        let millis: f64 = js!( return Date.now(); ).try_into().unwrap();
+        (millis / 1000.0) as u64
+    }
+}
+
 fn main() {
     stdweb::initialize();
 
@@ -33,15 +47,23 @@ fn main() {
     let ctx: CanvasRenderingContext2d = canvas.get_context().unwrap();
 
     let gameboy = Rc::new(RefCell::new(GameBoy::new()));
+    gameboy.borrow_mut().set_clock(Rc::new(BrowserClock));
     add_load_rom_event_handler(gameboy.clone());
-    async_render_loop(ctx, gameboy.clone());
+    add_palette_event_handler(gameboy.clone());
+    add_unload_event_handler(gameboy.clone());
+    async_render_loop(ctx, gameboy.clone(), 0);
 
     stdweb::event_loop();
 }
 
-fn async_render_loop(ctx: CanvasRenderingContext2d, gameboy: Rc<RefCell<GameBoy>>) {
+fn async_render_loop(ctx: CanvasRenderingContext2d, gameboy: Rc<RefCell<GameBoy>>, frame: u32) {
     web::window().request_animation_frame(move |_| {
         let screen = gameboy.borrow_mut().step();
+        let rumble = gameboy.borrow().rumble();
+
+        if frame.is_multiple_of(SAVE_INTERVAL_FRAMES) && gameboy.borrow().save_dirty() {
+            store_save(&mut gameboy.borrow_mut());
+        }
 
         js! {
             @{&ctx}.putImageData(new ImageData(
@@ -49,12 +71,75 @@ fn async_render_loop(ctx: CanvasRenderingContext2d, gameboy: Rc<RefCell<GameBoy>
                 @{SCREEN_W},
                 @{SCREEN_H},
             ), 0, 0);
+
+            // Keep vibrating a little longer than a frame while the motor is on
+            if (navigator.vibrate) {
+                navigator.vibrate(@{rumble} ? 50 : 0);
+            }
         }
 
-        async_render_loop(ctx, gameboy);
+        async_render_loop(ctx, gameboy, frame.wrapping_add(1));
     });
 }
 
+// save_key returns the key of the local storage which the save of the game loaded is kept under
+fn save_key(gameboy: &GameBoy) -> String {
+    let header = gameboy.header();
+    format!("save:{}:{:04X}", header.title, header.global_checksum)
+}
+
+// store_save writes the battery backed RAM to the local storage as a hex string
+fn store_save(gameboy: &mut GameBoy) {
+    let save = match gameboy.export_save() {
+        Some(save) => save,
+        None => return,
+    };
+
+    let hex: String = save.iter().map(|b| format!("{:02x}", b)).collect();
+    if web::window().local_storage().insert(&save_key(gameboy), &hex).is_err() {
+        js! { console.error("Failed to store the save"); }
+    }
+}
+
+// restore_save reads the save of the game loaded back from the local storage, if there is one
+fn restore_save(gameboy: &mut GameBoy) {
+    let hex = match web::window().local_storage().get(&save_key(gameboy)) {
+        Some(hex) => hex,
+        None => return,
+    };
+
+    let save: Option<Vec<u8>> = (0..hex.len())
+        .step_by(2)
+        .map(|i| hex.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+        .collect();
+    let result = match save {
+        Some(save) => gameboy.import_save(&save).map_err(|err| err.to_string()),
+        None => Err("broken data".to_string()),
+    };
+
+    if let Err(err) = result {
+        let message = format!("Failed to restore the save: {}", err);
+        js! { console.error(@{message}); }
+    }
+}
+
+// add_unload_event_handler stores the save when the page is closed or hidden, as the save written periodically
+// can be up to a second old
+fn add_unload_event_handler(gameboy: Rc<RefCell<GameBoy>>) {
+    let flush = move || {
+        if gameboy.borrow().save_dirty() {
+            store_save(&mut gameboy.borrow_mut());
+        }
+    };
+
+    // pagehide is also sent on mobile browsers, which often leave pages without beforeunload
+    js! {
+        var flush = @{flush};
+        window.addEventListener("beforeunload", function() { flush(); });
+        window.addEventListener("pagehide", function() { flush(); });
+    }
+}
+
 fn add_load_rom_event_handler(gameboy: Rc<RefCell<GameBoy>>) {
     let load_rom_button = web::document().get_element_by_id("load-rom").unwrap();
     load_rom_button.add_event_listener(move |event: ChangeEvent| {
@@ -73,12 +158,55 @@ fn add_load_rom_event_handler(gameboy: Rc<RefCell<GameBoy>>) {
             }
             .into();
 
-            let cart = Cartridge::new(rom);
-            gameboy.borrow_mut().pause();
-            gameboy.borrow_mut().load(cart);
+            // The save of the current game is lost unless it is stored before the next game replaces it
+            if gameboy.borrow().save_dirty() {
+                store_save(&mut gameboy.borrow_mut());
+            }
+
+            // A broken file must not stop the page, so the current game keeps running on errors
+            let result = gameboy.borrow_mut().load(rom);
+            if let Err(err) = result {
+                let message = format!("Failed to load the ROM: {}", err);
+                js! {
+                    console.error(@{&message});
+                    alert(@{&message});
+                }
+                return;
+            }
+
+            restore_save(&mut gameboy.borrow_mut());
+
+            let title = gameboy.borrow().header().title.clone();
+            js! { document.title = @{title} + " - Game Boy Emulator"; }
             gameboy.borrow_mut().unpause();
         }));
 
         reader.read_as_array_buffer(&file).unwrap();
     });
 }
+
+fn add_palette_event_handler(gameboy: Rc<RefCell<GameBoy>>) {
+    let select: SelectElement = web::document()
+        .get_element_by_id("palette")
+        .unwrap()
+        .try_into()
+        .unwrap();
+
+    for (i, (name, _)) in palette::PRESETS.iter().enumerate() {
+        js! {
+            @{&select}.add(new Option(@{*name}, @{i as u32}));
+        }
+    }
+
+    select.add_event_listener(move |event: ChangeEvent| {
+        let select: SelectElement = event.target().unwrap().try_into().unwrap();
+        let preset = select
+            .value()
+            .and_then(|value| value.parse::<usize>().ok())
+            .and_then(|i| palette::PRESETS.get(i));
+
+        if let Some((_, palette)) = preset {
+            gameboy.borrow_mut().set_palette(*palette);
+        }
+    });
+}